@@ -0,0 +1,172 @@
+use rustc_ast::expand::allocator::{AllocatorKind, AllocatorTy, ALLOCATOR_METHODS};
+use rustc_hir::def::DefKind;
+use rustc_middle::ty::{Instance, TyCtxt};
+use rustc_session::config::OomStrategy;
+use rustc_span::{def_id::LOCAL_CRATE, symbol::sym};
+
+use llvm_sys::{core::*, prelude::*, LLVMLinkage};
+
+use std::ffi::CString;
+
+use crate::{
+    c_string,
+    codegen::get_fn,
+    ty::{i8p_type, isize_type},
+};
+
+/// Returns the allocator that `__rust_alloc` and friends forward to.
+///
+/// Rustc only needs the shim when it links a final artifact, but `--execute` also runs libraries,
+/// so this falls back to the default allocator unless some crate defines a `#[global_allocator]`.
+pub fn allocator_kind<'tcx>(tcx: TyCtxt<'tcx>) -> AllocatorKind {
+    tcx.allocator_kind(()).unwrap_or_else(|| {
+        let has_global_allocator = std::iter::once(LOCAL_CRATE)
+            .chain(tcx.crates(()).iter().copied())
+            .any(|cnum| tcx.has_global_allocator(cnum));
+        if has_global_allocator {
+            AllocatorKind::Global
+        } else {
+            AllocatorKind::Default
+        }
+    })
+}
+
+/// Emits the allocator shim: `__rust_alloc`, `__rust_dealloc`, `__rust_realloc` and
+/// `__rust_alloc_zeroed`, which forward to `__rg_*` for a `#[global_allocator]` or to `__rdl_*`
+/// otherwise, along with `__rust_alloc_error_handler`.
+pub unsafe fn codegen_allocator<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    kind: AllocatorKind,
+) {
+    let usize_llty = isize_type(llcx, tcx);
+    let i8p = i8p_type(llcx);
+    let void = LLVMVoidTypeInContext(llcx);
+
+    for method in ALLOCATOR_METHODS {
+        let mut inputs = Vec::new();
+        for input in method.inputs {
+            match input {
+                AllocatorTy::Layout => {
+                    inputs.push(usize_llty); // size
+                    inputs.push(usize_llty); // align
+                }
+                AllocatorTy::Ptr => inputs.push(i8p),
+                AllocatorTy::Usize => inputs.push(usize_llty),
+                AllocatorTy::ResultPtr | AllocatorTy::Unit => unreachable!(),
+            }
+        }
+        let output = match method.output {
+            AllocatorTy::ResultPtr => i8p,
+            AllocatorTy::Unit => void,
+            AllocatorTy::Layout | AllocatorTy::Ptr | AllocatorTy::Usize => unreachable!(),
+        };
+
+        let name = format!("__rust_{}", method.name);
+        let callee = kind.fn_name(method.name);
+        codegen_forwarder(llcx, llmod, tcx, &name, &callee, &mut inputs, output);
+    }
+
+    // An `#[alloc_error_handler]` is reachable through the `oom` lang item.
+    let handler_kind = if tcx.lang_items().oom().is_some() {
+        AllocatorKind::Global
+    } else {
+        AllocatorKind::Default
+    };
+    codegen_forwarder(
+        llcx,
+        llmod,
+        tcx,
+        "__rust_alloc_error_handler",
+        &handler_kind.fn_name(sym::oom),
+        &mut [usize_llty, usize_llty],
+        void,
+    );
+
+    // `-Z oom=panic` makes the default handler panic instead of aborting.
+    let should_panic = c_string!(OomStrategy::SYMBOL);
+    let mut global = LLVMGetNamedGlobal(llmod, should_panic.as_ptr());
+    if global.is_null() {
+        let i8 = LLVMInt8TypeInContext(llcx);
+        global = LLVMAddGlobal(llmod, i8, should_panic.as_ptr());
+        let val = tcx.sess.opts.unstable_opts.oom.should_panic();
+        LLVMSetInitializer(global, LLVMConstInt(i8, val as u64, 0));
+        LLVMSetGlobalConstant(global, 1);
+    }
+}
+
+/// Defines `name` as a function of the given type which tail calls `callee` with its arguments.
+unsafe fn codegen_forwarder<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    name: &str,
+    callee: &str,
+    inputs: &mut [LLVMTypeRef],
+    output: LLVMTypeRef,
+) {
+    let llfn_ty = LLVMFunctionType(output, inputs.as_mut_ptr(), inputs.len() as _, 0);
+
+    // Calls to the shim may already have declared it.
+    let fn_name = c_string!(name);
+    let mut llfn = LLVMGetNamedFunction(llmod, fn_name.as_ptr());
+    if llfn.is_null() {
+        llfn = LLVMAddFunction(llmod, fn_name.as_ptr(), llfn_ty);
+    } else if !LLVMGetFirstBasicBlock(llfn).is_null() {
+        return;
+    }
+    LLVMSetLinkage(llfn, LLVMLinkage::LLVMExternalLinkage);
+
+    let callee = get_local_fn(llcx, llmod, tcx, callee).unwrap_or_else(|| {
+        let callee = c_string!(callee);
+        let llcallee = LLVMGetNamedFunction(llmod, callee.as_ptr());
+        if llcallee.is_null() {
+            LLVMAddFunction(llmod, callee.as_ptr(), llfn_ty)
+        } else {
+            llcallee
+        }
+    });
+
+    let llbx = LLVMCreateBuilderInContext(llcx);
+    let entry = LLVMAppendBasicBlockInContext(llcx, llfn, c_string!("entry").as_ptr());
+    LLVMPositionBuilderAtEnd(llbx, entry);
+
+    let mut args = (0..inputs.len())
+        .map(|idx| LLVMGetParam(llfn, idx as core::ffi::c_uint))
+        .collect::<Vec<_>>();
+    let ret = LLVMBuildCall2(
+        llbx,
+        llfn_ty,
+        callee,
+        args.as_mut_ptr(),
+        args.len() as core::ffi::c_uint,
+        c_string!("").as_ptr(),
+    );
+    LLVMSetTailCall(ret, 1);
+    if output == LLVMVoidTypeInContext(llcx) {
+        LLVMBuildRetVoid(llbx);
+    } else {
+        LLVMBuildRet(llbx, ret);
+    }
+
+    LLVMDisposeBuilder(llbx);
+}
+
+/// `#[global_allocator]` and `#[alloc_error_handler]` expand to `__rg_*` functions in the crate
+/// that defines them. Those in the local crate are generated like any other function.
+unsafe fn get_local_fn<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    symbol: &str,
+) -> Option<LLVMValueRef> {
+    let def_id = tcx
+        .hir_crate_items(())
+        .items()
+        .map(|item| item.def_id.to_def_id())
+        .filter(|def_id| tcx.def_kind(*def_id) == DefKind::Fn)
+        .find(|def_id| tcx.symbol_name(Instance::mono(tcx, *def_id)).name == symbol)?;
+
+    Some(get_fn(llcx, llmod, tcx, Instance::mono(tcx, def_id)))
+}
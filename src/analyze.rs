@@ -0,0 +1,235 @@
+use rustc_index::{bit_set::BitSet, vec::IndexVec};
+use rustc_middle::{
+    middle::codegen_fn_attrs::CodegenFnAttrFlags,
+    mir::{
+        traversal,
+        visit::{MutatingUseContext, NonMutatingUseContext, PlaceContext, Visitor},
+        BasicBlock, Local, Location, Operand, Place, ProjectionElem, Rvalue, Terminator,
+        TerminatorKind, RETURN_PLACE,
+    },
+    ty::{Instance, ParamEnv, TyKind},
+};
+use rustc_target::{abi::Abi, spec::abi::Abi as FnAbi};
+
+use crate::FunctionCx;
+
+/// Where a local is assigned.
+#[derive(Clone, Copy)]
+enum LocalDef {
+    None,
+    /// Arguments are assigned on entry.
+    Argument,
+    Assign(Location),
+    /// Assigned more than once, or in a way that needs memory.
+    Many,
+}
+
+/// Returns the locals which need a stack slot. All others are kept as SSA values, which requires
+/// them to be immediates that are assigned exactly once before all of their uses, and never
+/// borrowed or projected into.
+pub(crate) fn non_ssa_locals<'tcx>(fx: &FunctionCx<'tcx>) -> BitSet<Local> {
+    let mut analyzer = LocalAnalyzer {
+        fx,
+        defs: IndexVec::from_elem(LocalDef::None, &fx.mir.local_decls),
+        uses: Vec::new(),
+    };
+    for arg in fx.mir.args_iter() {
+        analyzer.defs[arg] = LocalDef::Argument;
+    }
+    // A spread argument is assembled from several parameters.
+    if let Some(spread_arg) = fx.mir.spread_arg {
+        analyzer.not_ssa(spread_arg);
+    }
+    // Unreachable blocks are not generated, so their uses do not count.
+    for (bb, data) in traversal::reverse_postorder(fx.mir) {
+        analyzer.visit_basic_block_data(bb, data);
+    }
+
+    let mut non_ssa_locals = BitSet::new_empty(fx.mir.local_decls.len());
+    for (local, def) in analyzer.defs.iter_enumerated() {
+        let layout = fx.locals[local].layout();
+        // Zero-sized locals have neither storage nor a value.
+        if layout.is_zst() {
+            continue;
+        }
+        let is_immediate = matches!(
+            layout.abi,
+            Abi::Scalar(_) | Abi::ScalarPair(..) | Abi::Vector { .. }
+        );
+        if !is_immediate || !matches!(def, LocalDef::Argument | LocalDef::Assign(_)) {
+            non_ssa_locals.insert(local);
+        }
+    }
+
+    let dominators = fx.mir.dominators();
+    for (local, location) in analyzer.uses {
+        if let LocalDef::Assign(def) = analyzer.defs[local] {
+            if !def.dominates(location, &dominators) {
+                non_ssa_locals.insert(local);
+            }
+        }
+    }
+
+    non_ssa_locals
+}
+
+/// Returns the blocks from which every path ends in `Unreachable` or in a call to a `#[cold]`
+/// function, such as the ones that panic.
+pub(crate) fn cold_blocks<'tcx>(fx: &FunctionCx<'tcx>) -> BitSet<BasicBlock> {
+    let mut cold_blocks = BitSet::new_empty(fx.mir.basic_blocks.len());
+    // Successors are visited first, except along back edges, which are taken to be hot.
+    for (bb, data) in traversal::postorder(fx.mir) {
+        let is_cold = match &data.terminator().kind {
+            TerminatorKind::Unreachable => true,
+            TerminatorKind::Call { func, .. } if calls_cold_fn(fx, func) => true,
+            kind => {
+                let mut successors = kind
+                    .successors()
+                    .filter(|succ| !fx.mir.basic_blocks[*succ].is_cleanup)
+                    .peekable();
+                successors.peek().is_some() && successors.all(|succ| cold_blocks.contains(succ))
+            }
+        };
+        if is_cold {
+            cold_blocks.insert(bb);
+        }
+    }
+
+    cold_blocks
+}
+
+fn calls_cold_fn<'tcx>(fx: &FunctionCx<'tcx>, func: &Operand<'tcx>) -> bool {
+    let func_ty = fx.monomorphize(func.ty(fx.mir, fx.tcx));
+    let (def_id, substs) = match func_ty.kind() {
+        TyKind::FnDef(def_id, substs) => (*def_id, *substs),
+        _ => return false,
+    };
+    let instance = Instance::resolve(fx.tcx, ParamEnv::reveal_all(), def_id, substs)
+        .unwrap()
+        .unwrap();
+    fx.tcx
+        .codegen_fn_attrs(instance.def_id())
+        .flags
+        .contains(CodegenFnAttrFlags::COLD)
+}
+
+/// Returns the value expected of the locals that hold the result of `likely` or `unlikely`.
+pub(crate) fn expected_values<'tcx>(fx: &FunctionCx<'tcx>) -> IndexVec<Local, Option<bool>> {
+    let mut expected_values = IndexVec::from_elem(None, &fx.mir.local_decls);
+    for data in fx.mir.basic_blocks.iter() {
+        let (func, destination) = match &data.terminator().kind {
+            TerminatorKind::Call {
+                func, destination, ..
+            } => (func, destination),
+            _ => continue,
+        };
+        let func_ty = fx.monomorphize(func.ty(fx.mir, fx.tcx));
+        if let (TyKind::FnDef(def_id, _), Some(local)) = (func_ty.kind(), destination.as_local()) {
+            if fx.tcx.fn_sig(*def_id).abi() != FnAbi::RustIntrinsic {
+                continue;
+            }
+            match fx.tcx.item_name(*def_id).as_str() {
+                "likely" => expected_values[local] = Some(true),
+                "unlikely" => expected_values[local] = Some(false),
+                _ => {}
+            }
+        }
+    }
+
+    expected_values
+}
+
+struct LocalAnalyzer<'a, 'tcx> {
+    fx: &'a FunctionCx<'tcx>,
+    defs: IndexVec<Local, LocalDef>,
+    uses: Vec<(Local, Location)>,
+}
+
+impl LocalAnalyzer<'_, '_> {
+    fn define(&mut self, local: Local, location: Location) {
+        self.defs[local] = match self.defs[local] {
+            LocalDef::None => LocalDef::Assign(location),
+            _ => LocalDef::Many,
+        };
+    }
+
+    fn not_ssa(&mut self, local: Local) {
+        self.defs[local] = LocalDef::Many;
+    }
+}
+
+impl<'tcx> Visitor<'tcx> for LocalAnalyzer<'_, 'tcx> {
+    fn visit_assign(&mut self, place: &Place<'tcx>, rvalue: &Rvalue<'tcx>, location: Location) {
+        // The other rvalues are written to memory piece by piece.
+        let is_immediate = matches!(
+            rvalue,
+            Rvalue::Use(_)
+                | Rvalue::CopyForDeref(_)
+                | Rvalue::BinaryOp(..)
+                | Rvalue::UnaryOp(..)
+                | Rvalue::Ref(..)
+                | Rvalue::AddressOf(..)
+                | Rvalue::Cast(..)
+                | Rvalue::Len(_)
+                | Rvalue::NullaryOp(..)
+                | Rvalue::ShallowInitBox(..)
+                | Rvalue::Discriminant(_)
+                | Rvalue::ThreadLocalRef(_)
+        );
+        if let (false, Some(local)) = (is_immediate, place.as_local()) {
+            self.not_ssa(local);
+        }
+
+        self.super_assign(place, rvalue, location);
+    }
+
+    fn visit_terminator(&mut self, terminator: &Terminator<'tcx>, location: Location) {
+        match &terminator.kind {
+            // Some intrinsics write their result through a pointer to the destination.
+            TerminatorKind::Call {
+                func, destination, ..
+            } => {
+                let func_ty = self.fx.monomorphize(func.ty(self.fx.mir, self.fx.tcx));
+                if let (TyKind::FnDef(def_id, _), Some(local)) =
+                    (func_ty.kind(), destination.as_local())
+                {
+                    if let FnAbi::RustIntrinsic | FnAbi::PlatformIntrinsic =
+                        self.fx.tcx.fn_sig(*def_id).abi()
+                    {
+                        self.not_ssa(local);
+                    }
+                }
+            }
+            TerminatorKind::Return => self.uses.push((RETURN_PLACE, location)),
+            _ => {}
+        }
+
+        self.super_terminator(terminator, location);
+    }
+
+    fn visit_place(&mut self, place: &Place<'tcx>, context: PlaceContext, location: Location) {
+        for elem in place.projection {
+            if let ProjectionElem::Index(local) = elem {
+                self.uses.push((local, location));
+            }
+        }
+        // A place behind a pointer only uses the pointer.
+        if let Some(ProjectionElem::Deref) = place.projection.first() {
+            self.uses.push((place.local, location));
+            return;
+        }
+
+        match context {
+            PlaceContext::MutatingUse(MutatingUseContext::Store | MutatingUseContext::Call)
+                if place.projection.is_empty() =>
+            {
+                self.define(place.local, location)
+            }
+            PlaceContext::NonMutatingUse(
+                NonMutatingUseContext::Copy | NonMutatingUseContext::Move,
+            ) if place.projection.is_empty() => self.uses.push((place.local, location)),
+            PlaceContext::NonUse(_) | PlaceContext::MutatingUse(MutatingUseContext::Retag) => {}
+            _ => self.not_ssa(place.local),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use rustc_ast::{InlineAsmOptions, InlineAsmTemplatePiece};
+use rustc_middle::{
+    mir::{self, InlineAsmOperand},
+    ty::{Instance, ParamEnv, TyKind},
+};
+use rustc_target::asm::{InlineAsmRegClass, InlineAsmRegOrRegClass, X86InlineAsmRegClass};
+
+use llvm_sys::{core::*, prelude::*, LLVMAttributeFunctionIndex, LLVMInlineAsmDialect};
+
+use std::ffi::CString;
+
+use crate::{c_string, codegen::get_fn, consts::get_static, ty::ty_to_llvm_type, FunctionCx};
+
+impl<'tcx> FunctionCx<'tcx> {
+    /// Lowers `asm!` to a call to an LLVM inline assembly value. Outputs come first in the
+    /// constraint string, followed by inputs and clobbers, and operands in the template refer to
+    /// their position in that order.
+    pub(crate) unsafe fn codegen_inline_asm(
+        &mut self,
+        template: &[InlineAsmTemplatePiece],
+        operands: &[InlineAsmOperand<'tcx>],
+        options: InlineAsmOptions,
+        destination: Option<mir::BasicBlock>,
+    ) {
+        let llname = c_string!("");
+
+        let mut output_constraints = Vec::new();
+        let mut output_places = Vec::new();
+        let mut output_types = Vec::new();
+        let mut clobbers = Vec::new();
+        // Maps each MIR operand to its LLVM operand, or to the text it is substituted with.
+        let mut operand_indices = vec![None; operands.len()];
+        for (idx, operand) in operands.iter().enumerate() {
+            let (reg, late, place) = match operand {
+                InlineAsmOperand::Out { reg, late, place } => (reg, late, place.as_ref()),
+                InlineAsmOperand::InOut {
+                    reg,
+                    late,
+                    out_place,
+                    ..
+                } => (reg, late, out_place.as_ref()),
+                _ => continue,
+            };
+            // Outputs to explicit registers that are never read are clobbers.
+            if let (InlineAsmRegOrRegClass::Reg(_), None) = (reg, place) {
+                if let InlineAsmOperand::Out { .. } = operand {
+                    clobbers.push(format!("~{}", reg_constraint(reg)));
+                    continue;
+                }
+            }
+
+            let early_clobber = if *late { "" } else { "&" };
+            output_constraints.push(format!("={}{}", early_clobber, reg_constraint(reg)));
+            operand_indices[idx] = Some(output_places.len().to_string());
+            match place {
+                Some(place) => {
+                    let place = self.codegen_place(place);
+                    output_types.push(ty_to_llvm_type(self.llcx, self.tcx, place.ty()));
+                    output_places.push(Some(place));
+                }
+                None => {
+                    output_types.push(dummy_output_type(self.llcx, reg.reg_class()));
+                    output_places.push(None);
+                }
+            }
+        }
+
+        let mut input_constraints = Vec::new();
+        let mut inputs = Vec::new();
+        for (idx, operand) in operands.iter().enumerate() {
+            let llidx = (output_places.len() + inputs.len()).to_string();
+            match operand {
+                InlineAsmOperand::In { reg, value } => {
+                    let value = self.codegen_operand(value).load_scalar(self.llbx);
+                    input_constraints.push(reg_constraint(reg));
+                    inputs.push(*value.llval());
+                    operand_indices[idx] = Some(llidx);
+                }
+                // The input is tied to the output of the same operand.
+                InlineAsmOperand::InOut { in_value, .. } => {
+                    let value = self.codegen_operand(in_value).load_scalar(self.llbx);
+                    input_constraints.push(operand_indices[idx].clone().unwrap());
+                    inputs.push(*value.llval());
+                }
+                // Symbols are passed as addresses, and printed without the punctuation of an
+                // immediate by the `c` modifier.
+                InlineAsmOperand::SymFn { value } => {
+                    let (def_id, substs) = match self.monomorphize(value.ty()).kind() {
+                        TyKind::FnDef(def_id, substs) => (*def_id, *substs),
+                        _ => unreachable!(),
+                    };
+                    let instance = Instance::resolve_for_fn_ptr(
+                        self.tcx,
+                        ParamEnv::reveal_all(),
+                        def_id,
+                        substs,
+                    )
+                    .unwrap();
+                    input_constraints.push("s".to_string());
+                    inputs.push(get_fn(self.llcx, self.llmod, self.tcx, instance));
+                    operand_indices[idx] = Some(format!("{}:c", llidx));
+                }
+                InlineAsmOperand::SymStatic { def_id } => {
+                    input_constraints.push("s".to_string());
+                    inputs.push(get_static(self.llcx, self.llmod, self.tcx, *def_id));
+                    operand_indices[idx] = Some(format!("{}:c", llidx));
+                }
+                _ => {}
+            }
+        }
+
+        let mut asm = String::new();
+        for piece in template {
+            match piece {
+                InlineAsmTemplatePiece::String(string) => asm.push_str(&string.replace('$', "$$")),
+                InlineAsmTemplatePiece::Placeholder {
+                    operand_idx,
+                    modifier,
+                    ..
+                } => match &operands[*operand_idx] {
+                    // Constants are substituted into the template as text.
+                    InlineAsmOperand::Const { value } => {
+                        let ty = self.monomorphize(value.ty());
+                        let layout = self.layout_of(ty);
+                        let bits = self
+                            .monomorphize(value.literal)
+                            .eval(self.tcx, ParamEnv::reveal_all())
+                            .try_to_scalar_int()
+                            .unwrap()
+                            .to_bits(layout.size)
+                            .unwrap();
+                        if ty.is_signed() {
+                            asm.push_str(&(layout.size.sign_extend(bits) as i128).to_string());
+                        } else {
+                            asm.push_str(&bits.to_string());
+                        }
+                    }
+                    InlineAsmOperand::In { reg, .. }
+                    | InlineAsmOperand::Out { reg, .. }
+                    | InlineAsmOperand::InOut { reg, .. } => {
+                        let llidx = operand_indices[*operand_idx].as_ref().unwrap();
+                        match modifier_to_llvm(reg.reg_class(), *modifier) {
+                            Some(modifier) => asm.push_str(&format!("${{{}:{}}}", llidx, modifier)),
+                            None => asm.push_str(&format!("${{{}}}", llidx)),
+                        }
+                    }
+                    _ => {
+                        let llidx = operand_indices[*operand_idx].as_ref().unwrap();
+                        asm.push_str(&format!("${{{}}}", llidx));
+                    }
+                },
+            }
+        }
+
+        if !options.contains(InlineAsmOptions::PRESERVES_FLAGS) {
+            clobbers.extend(["~{dirflag}", "~{fpsr}", "~{flags}"].map(String::from));
+        }
+        if !options.intersects(InlineAsmOptions::NOMEM | InlineAsmOptions::READONLY) {
+            clobbers.push("~{memory}".to_string());
+        }
+        let constraints = output_constraints
+            .into_iter()
+            .chain(input_constraints)
+            .chain(clobbers)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let output_llty = match output_types.len() {
+            0 => LLVMVoidTypeInContext(self.llcx),
+            1 => output_types[0],
+            _ => LLVMStructTypeInContext(
+                self.llcx,
+                output_types.as_mut_ptr(),
+                output_types.len() as core::ffi::c_uint,
+                0,
+            ),
+        };
+        let mut input_types = inputs
+            .iter()
+            .map(|input| LLVMTypeOf(*input))
+            .collect::<Vec<_>>();
+        let asm_llty = LLVMFunctionType(
+            output_llty,
+            input_types.as_mut_ptr(),
+            input_types.len() as core::ffi::c_uint,
+            0,
+        );
+        let dialect = if options.contains(InlineAsmOptions::ATT_SYNTAX) {
+            LLVMInlineAsmDialect::LLVMInlineAsmDialectATT
+        } else {
+            LLVMInlineAsmDialect::LLVMInlineAsmDialectIntel
+        };
+        let asm_llval = LLVMGetInlineAsm(
+            asm_llty,
+            asm.as_ptr() as *mut _,
+            asm.len(),
+            constraints.as_ptr() as *mut _,
+            constraints.len(),
+            !options.contains(InlineAsmOptions::PURE) as LLVMBool,
+            0,
+            dialect,
+            0,
+        );
+        let ret = LLVMBuildCall2(
+            self.llbx,
+            asm_llty,
+            asm_llval,
+            inputs.as_mut_ptr(),
+            inputs.len() as core::ffi::c_uint,
+            llname.as_ptr(),
+        );
+
+        let mut attributes = vec!["nounwind"];
+        if options.contains(InlineAsmOptions::PURE) {
+            if options.contains(InlineAsmOptions::NOMEM) {
+                attributes.push("readnone");
+            } else if options.contains(InlineAsmOptions::READONLY) {
+                attributes.push("readonly");
+            }
+        }
+        for attribute in attributes {
+            let kind =
+                LLVMGetEnumAttributeKindForName(attribute.as_ptr() as *const _, attribute.len());
+            let attribute = LLVMCreateEnumAttribute(self.llcx, kind, 0);
+            LLVMAddCallSiteAttribute(ret, LLVMAttributeFunctionIndex, attribute);
+        }
+
+        for (idx, place) in output_places.into_iter().enumerate() {
+            if let Some(place) = place {
+                let llval = if output_types.len() == 1 {
+                    ret
+                } else {
+                    LLVMBuildExtractValue(self.llbx, ret, idx as core::ffi::c_uint, llname.as_ptr())
+                };
+                place.store_llval(self.llbx, llval);
+            }
+        }
+
+        match destination {
+            Some(target) => {
+                LLVMBuildBr(self.llbx, self.basic_blocks[target]);
+            }
+            None => {
+                LLVMBuildUnreachable(self.llbx);
+            }
+        }
+    }
+}
+
+/// Returns the LLVM constraint for an explicit register, e.g. `{eax}`, or for a register class.
+fn reg_constraint(reg: &InlineAsmRegOrRegClass) -> String {
+    let reg_class = match reg {
+        InlineAsmRegOrRegClass::Reg(reg) => return format!("{{{}}}", reg.name()),
+        InlineAsmRegOrRegClass::RegClass(reg_class) => reg_class,
+    };
+    let constraint = match reg_class {
+        InlineAsmRegClass::X86(reg_class) => match reg_class {
+            X86InlineAsmRegClass::reg => "r",
+            X86InlineAsmRegClass::reg_abcd => "Q",
+            X86InlineAsmRegClass::reg_byte => "q",
+            X86InlineAsmRegClass::xmm_reg | X86InlineAsmRegClass::ymm_reg => "x",
+            X86InlineAsmRegClass::zmm_reg => "v",
+            X86InlineAsmRegClass::kreg => "^Yk",
+            _ => todo!("register class `{}`", reg_class.name()),
+        },
+        _ => todo!("register class `{}`", reg_class.name()),
+    };
+
+    constraint.to_string()
+}
+
+/// Translates the template modifier of a register operand to LLVM's, e.g. `e` for `eax` becomes
+/// `k`. Without a modifier, general purpose registers are printed at their full 64 bits.
+fn modifier_to_llvm(reg_class: InlineAsmRegClass, modifier: Option<char>) -> Option<char> {
+    match reg_class {
+        InlineAsmRegClass::X86(X86InlineAsmRegClass::reg | X86InlineAsmRegClass::reg_abcd) => {
+            match modifier {
+                None => Some('q'),
+                Some('l') => Some('b'),
+                Some('h') => Some('h'),
+                Some('x') => Some('w'),
+                Some('e') => Some('k'),
+                Some('r') => Some('q'),
+                _ => unreachable!(),
+            }
+        }
+        InlineAsmRegClass::X86(
+            reg_class @ (X86InlineAsmRegClass::xmm_reg
+            | X86InlineAsmRegClass::ymm_reg
+            | X86InlineAsmRegClass::zmm_reg),
+        ) => match (reg_class, modifier) {
+            (X86InlineAsmRegClass::xmm_reg, None) | (_, Some('x')) => Some('x'),
+            (X86InlineAsmRegClass::ymm_reg, None) | (_, Some('y')) => Some('t'),
+            (X86InlineAsmRegClass::zmm_reg, None) | (_, Some('z')) => Some('g'),
+            _ => unreachable!(),
+        },
+        InlineAsmRegClass::X86(X86InlineAsmRegClass::reg_byte | X86InlineAsmRegClass::kreg) => None,
+        _ => modifier,
+    }
+}
+
+/// Returns the type of a discarded output in a register of `reg_class`, which only needs to fit.
+unsafe fn dummy_output_type(llcx: LLVMContextRef, reg_class: InlineAsmRegClass) -> LLVMTypeRef {
+    match reg_class {
+        InlineAsmRegClass::X86(X86InlineAsmRegClass::reg | X86InlineAsmRegClass::reg_abcd) => {
+            LLVMInt32TypeInContext(llcx)
+        }
+        InlineAsmRegClass::X86(X86InlineAsmRegClass::reg_byte) => LLVMInt8TypeInContext(llcx),
+        InlineAsmRegClass::X86(
+            X86InlineAsmRegClass::xmm_reg
+            | X86InlineAsmRegClass::ymm_reg
+            | X86InlineAsmRegClass::zmm_reg,
+        ) => LLVMFloatTypeInContext(llcx),
+        InlineAsmRegClass::X86(X86InlineAsmRegClass::kreg) => LLVMInt16TypeInContext(llcx),
+        _ => todo!("register class `{}`", reg_class.name()),
+    }
+}
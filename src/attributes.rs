@@ -0,0 +1,152 @@
+use rustc_middle::{
+    middle::codegen_fn_attrs::CodegenFnAttrFlags,
+    ty::{FnSig, Instance, ParamEnv, Ty, TyCtxt, TyKind},
+};
+use rustc_span::DUMMY_SP;
+use rustc_target::{
+    abi::{Abi, Integer, Primitive, Scalar, WrappingRange},
+    spec::abi::Abi as FnAbi,
+};
+
+use llvm_sys::{
+    core::*, prelude::*, LLVMAttributeFunctionIndex, LLVMAttributeIndex, LLVMAttributeReturnIndex,
+    LLVMTypeKind,
+};
+
+use crate::ty::layout_of;
+
+/// Adds the attributes that follow from the types in `fn_sig` to `llfn`, the way rustc does.
+/// `#[cold]` functions are marked as such, which makes LLVM treat the paths calling them as cold.
+pub(crate) unsafe fn apply_fn_attributes<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    llfn: LLVMValueRef,
+    instance: Instance<'tcx>,
+    fn_sig: FnSig<'tcx>,
+) {
+    if tcx
+        .codegen_fn_attrs(instance.def_id())
+        .flags
+        .contains(CodegenFnAttrFlags::COLD)
+    {
+        add_attribute(llcx, llfn, LLVMAttributeFunctionIndex, "cold", 0);
+    }
+    let output = layout_of(tcx, fn_sig.output());
+    if output.abi.is_uninhabited() {
+        add_attribute(llcx, llfn, LLVMAttributeFunctionIndex, "noreturn", 0);
+    }
+    if !output.is_zst() {
+        for (name, val) in scalar_attributes(tcx, fn_sig.output(), fn_sig.abi, true) {
+            add_attribute(llcx, llfn, LLVMAttributeReturnIndex, name, val);
+        }
+    }
+
+    let inputs = fn_sig
+        .inputs()
+        .iter()
+        .filter(|input| !layout_of(tcx, **input).is_zst());
+    for (idx, input) in inputs.enumerate() {
+        for (name, val) in scalar_attributes(tcx, *input, fn_sig.abi, false) {
+            add_attribute(llcx, llfn, idx as LLVMAttributeIndex + 1, name, val);
+        }
+    }
+}
+
+/// Returns the attributes of a scalar of type `ty` passed to or returned from a function.
+fn scalar_attributes<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+    abi: FnAbi,
+    is_return: bool,
+) -> Vec<(&'static str, u64)> {
+    let layout = layout_of(tcx, ty);
+    let scalar = match layout.abi {
+        Abi::Scalar(scalar) => scalar,
+        _ => return Vec::new(),
+    };
+
+    let mut attributes = Vec::new();
+    if !scalar.is_uninit_valid() {
+        attributes.push(("noundef", 0));
+    }
+    match scalar.primitive() {
+        Primitive::Int(Integer::I8, false) if ty.is_bool() => attributes.push(("zeroext", 0)),
+        // The C calling convention extends integers narrower than 32 bits.
+        Primitive::Int(int, signed) if matches!(abi, FnAbi::C { .. }) && int < Integer::I32 => {
+            attributes.push((if signed { "signext" } else { "zeroext" }, 0));
+        }
+        _ => {}
+    }
+
+    let (pointee, is_mut) = match ty.kind() {
+        TyKind::Ref(_, pointee, mutbl) => (*pointee, mutbl.is_mut()),
+        TyKind::Adt(..) if ty.is_box() => (ty.boxed_ty(), true),
+        _ => return attributes,
+    };
+    let pointee_layout = layout_of(tcx, pointee);
+    // Pointers to unsized types are passed as a pair of data pointer and metadata.
+    if !pointee_layout.is_sized() {
+        return attributes;
+    }
+
+    attributes.push(("nonnull", 0));
+    attributes.push(("align", pointee_layout.align.abi.bytes()));
+    if pointee_layout.size.bytes() != 0 {
+        attributes.push(("dereferenceable", pointee_layout.size.bytes()));
+    }
+    if is_return {
+        return attributes;
+    }
+    let param_env = ParamEnv::reveal_all();
+    if ty.is_box() || (is_mut && pointee.is_unpin(tcx.at(DUMMY_SP), param_env)) {
+        // Self-referential generators may alias their `&mut self`, which is why they are `!Unpin`.
+        attributes.push(("noalias", 0));
+    } else if !is_mut && pointee.is_freeze(tcx.at(DUMMY_SP), param_env) {
+        attributes.push(("readonly", 0));
+    }
+
+    attributes
+}
+
+unsafe fn add_attribute(
+    llcx: LLVMContextRef,
+    llfn: LLVMValueRef,
+    idx: LLVMAttributeIndex,
+    name: &str,
+    val: u64,
+) {
+    let kind = LLVMGetEnumAttributeKindForName(name.as_ptr() as *const _, name.len());
+    let attribute = LLVMCreateEnumAttribute(llcx, kind, val);
+    LLVMAddAttributeAtIndex(llfn, idx, attribute);
+}
+
+/// Attaches the valid range of `scalar` to `load` as `!range` metadata, e.g. for `char` and enum
+/// tags. `bool` is lowered to `i1`, which has no values outside of its valid range to begin with.
+pub(crate) unsafe fn apply_range_metadata(load: LLVMValueRef, scalar: Scalar) {
+    let (int, valid_range) = match scalar {
+        Scalar::Initialized {
+            value: Primitive::Int(int, _),
+            valid_range,
+        } => (int, valid_range),
+        _ => return,
+    };
+    let llty = LLVMTypeOf(load);
+    if valid_range.is_full_for(int.size())
+        || LLVMGetTypeKind(llty) != LLVMTypeKind::LLVMIntegerTypeKind
+        || LLVMGetIntTypeWidth(llty) as u64 != int.size().bits()
+    {
+        return;
+    }
+
+    let WrappingRange { start, end } = valid_range;
+    // The range is half-open, and wraps around like the valid range itself.
+    let end = end.wrapping_add(1);
+    let llcx = LLVMGetTypeContext(llty);
+    let mut bounds = [start, end].map(|bound| {
+        let words = [bound as u64, (bound >> 64) as u64];
+        LLVMConstIntOfArbitraryPrecision(llty, 2, words.as_ptr())
+    });
+    let range = LLVMMDNodeInContext(llcx, bounds.as_mut_ptr(), bounds.len() as _);
+    let kind = LLVMGetMDKindIDInContext(llcx, "range".as_ptr() as *const _, "range".len() as _);
+    LLVMSetMetadata(load, kind, range);
+}
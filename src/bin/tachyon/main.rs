@@ -35,11 +35,13 @@ impl Callbacks for RunMode {
 fn main() {
     let mut args = std::env::args().collect::<Vec<_>>();
 
-    let mut callbacks = if args.get(1).map_or(false, |arg| arg == "--execute") {
-        args.remove(1);
-        RunMode::Execute
-    } else {
-        RunMode::Compile
+    // `--execute` may come anywhere, e.g. among the `compile-flags` of a ui test.
+    let mut callbacks = match args.iter().position(|arg| arg == "--execute") {
+        Some(idx) => {
+            args.remove(idx);
+            RunMode::Execute
+        }
+        None => RunMode::Compile,
     };
 
     let mut compiler = rustc_driver::RunCompiler::new(args.as_slice(), &mut callbacks);
@@ -8,8 +8,10 @@ use rustc_middle::ty::TyCtxt;
 use std::ffi::CString;
 
 use tachyon::{
+    allocator::{allocator_kind, codegen_allocator},
     c_string,
     codegen::{codegen_fn, execute_fn, optimize_fn},
+    passes::run_passes,
 };
 
 pub fn execute<'tcx>(tcx: TyCtxt<'tcx>) {
@@ -26,11 +28,12 @@ pub fn execute<'tcx>(tcx: TyCtxt<'tcx>) {
             let llcx = LLVMContextCreate();
             let llmod = LLVMModuleCreateWithNameInContext(c_string!("top").as_ptr(), llcx);
 
+            codegen_allocator(llcx, llmod, tcx, allocator_kind(tcx));
             codegen_fn(llcx, llmod, tcx, func);
-            let ee = optimize_fn(llmod);
+            let ee = optimize_fn(llmod, tcx.sess);
             LLVMDumpModule(llmod);
 
-            execute_fn(ee, func_name);
+            execute_fn(ee, func_name, tcx.fn_sig(func).output().skip_binder());
         }
     }
 }
@@ -48,7 +51,12 @@ pub fn compile<'tcx>(tcx: TyCtxt<'tcx>) {
             let llcx = LLVMContextCreate();
             let llmod = LLVMModuleCreateWithNameInContext(c_string!("top").as_ptr(), llcx);
 
+            // Libraries are linked against the shim of the final artifact instead.
+            if let Some(kind) = tcx.allocator_kind(()) {
+                codegen_allocator(llcx, llmod, tcx, kind);
+            }
             codegen_fn(llcx, llmod, tcx, func);
+            run_passes(llmod, tcx.sess);
             LLVMDumpModule(llmod);
         }
     }
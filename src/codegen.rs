@@ -1,11 +1,22 @@
-use rustc_const_eval::interpret::ConstValue;
-use rustc_hir::def_id::DefId;
+use rustc_const_eval::interpret::{Allocation, ConstValue, Scalar};
+use rustc_hir::{def_id::DefId, LangItem};
 use rustc_middle::{
     mir::{
-        self, traversal, BinOp, Operand, Rvalue, Statement, StatementKind, Terminator,
-        TerminatorKind,
+        self, traversal, AggregateKind, AssertKind, BinOp, CastKind, LocalKind,
+        NonDivergingIntrinsic, NullOp, Operand, ProjectionElem, Rvalue, Statement, StatementKind,
+        Terminator, TerminatorKind,
     },
-    ty::{layout::TyAndLayout, ParamEnv, Ty, TyCtxt, TyKind},
+    ty::{
+        adjustment::PointerCast, layout::TyAndLayout, ClosureKind, FloatTy, Instance, InstanceDef,
+        IntTy, ParamEnv, Ty, TyCtxt, TyKind, UintTy,
+    },
+};
+
+use rustc_session::Session;
+use rustc_span::{Span, Symbol};
+use rustc_target::{
+    abi::{Abi, Align, Primitive, TagEncoding, VariantIdx, Variants},
+    spec::abi::Abi as FnAbi,
 };
 
 use llvm_sys::{
@@ -16,18 +27,34 @@ use llvm_sys::{
     },
     prelude::*,
     target::{LLVM_InitializeNativeAsmPrinter, LLVM_InitializeNativeTarget},
-    transforms::scalar::LLVMAddScalarReplAggregatesPass,
     LLVMTypeKind,
 };
-use tracing::{debug, instrument, warn};
+use tracing::{debug, instrument};
 
-use std::{cell::OnceCell, ffi::CString};
+use std::{
+    cell::OnceCell,
+    ffi::{CStr, CString},
+    rc::Rc,
+};
 
 use crate::{
-    ty::{fn_sig_to_llvm_fn_type, ty_to_llvm_type},
+    analyze::{cold_blocks, expected_values, non_ssa_locals},
+    attributes::{apply_fn_attributes, apply_range_metadata},
+    consts::{add_const_alloc, const_gep, const_ptr_to_alloc, const_slice_data, get_static},
+    jit::{emulate_thread_locals, register_symbols, unresolved_symbols},
+    passes::run_passes,
+    ty::{
+        fn_sig_to_llvm_fn_type, has_pointer_metadata, i8p_type, instance_fn_sig, isize_type,
+        layout_cx, llvm_field_index, newtype_field, ty_to_llvm_type, virtual_fn_type,
+    },
+    vtable::{get_vtable, load_virtual_fn, load_vtable_entry, ALIGN, DROP_IN_PLACE, SIZE},
     FunctionCx,
 };
 
+/// The branch weights LLVM gives `llvm.expect`, for branches towards and away from cold blocks.
+const HOT_WEIGHT: u32 = 2000;
+const COLD_WEIGHT: u32 = 1;
+
 #[macro_export]
 macro_rules! c_string {
     ($string:expr) => {
@@ -38,30 +65,81 @@ macro_rules! c_string {
     };
 }
 
-#[instrument(skip(llcx, llmod, tcx, fn_id), fields(fn_name=?tcx.def_path_str(fn_id)))]
 pub unsafe fn codegen_fn<'tcx>(
     llcx: LLVMContextRef,
     llmod: LLVMModuleRef,
     tcx: TyCtxt<'tcx>,
     fn_id: DefId,
 ) -> LLVMValueRef {
-    let fn_sig = tcx.fn_sig(fn_id).no_bound_vars().unwrap();
-    let llfn_sig = fn_sig_to_llvm_fn_type(llcx, fn_sig);
+    codegen_instance(llcx, llmod, tcx, Instance::mono(tcx, fn_id))
+}
 
-    let fn_name = tcx.def_path_str(fn_id);
-    let fn_name = c_string!(fn_name);
-    let llfn = LLVMAddFunction(llmod, fn_name.as_ptr(), llfn_sig);
+#[instrument(skip(llcx, llmod, tcx, instance), fields(fn_name=%instance))]
+pub unsafe fn codegen_instance<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    instance: Instance<'tcx>,
+) -> LLVMValueRef {
+    let llfn = declare_fn(llcx, llmod, tcx, instance);
 
-    let mir = tcx.optimized_mir(fn_id);
+    let mir = tcx.instance_mir(instance.def);
 
-    let mut fx = FunctionCx::new(llcx, llmod, llfn, tcx, mir);
+    let mut fx = FunctionCx::new(llcx, llmod, llfn, tcx, instance, mir);
     fx.codegen_header();
     fx.codegen_body();
 
     llfn
 }
 
-pub unsafe fn optimize_fn<'tcx>(llmod: LLVMModuleRef) -> LLVMExecutionEngineRef {
+/// Returns the function for `instance`, generating it first if the module does not have it yet.
+pub(crate) unsafe fn get_fn<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    instance: Instance<'tcx>,
+) -> LLVMValueRef {
+    let fn_name = c_string!(instance_name(tcx, instance));
+    let llfn = LLVMGetNamedFunction(llmod, fn_name.as_ptr());
+    if !llfn.is_null() {
+        llfn
+    } else if tcx.is_foreign_item(instance.def_id()) {
+        // Foreign functions like `memcmp` have no body and are resolved by their symbol name.
+        declare_fn(llcx, llmod, tcx, instance)
+    } else {
+        codegen_instance(llcx, llmod, tcx, instance)
+    }
+}
+
+/// Adds the function for `instance` to the module, along with the attributes of its signature.
+unsafe fn declare_fn<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    instance: Instance<'tcx>,
+) -> LLVMValueRef {
+    let fn_sig = instance_fn_sig(tcx, instance);
+    let llfn_sig = fn_sig_to_llvm_fn_type(llcx, tcx, fn_sig);
+
+    let fn_name = c_string!(instance_name(tcx, instance));
+    let llfn = LLVMAddFunction(llmod, fn_name.as_ptr(), llfn_sig);
+    apply_fn_attributes(llcx, tcx, llfn, instance, fn_sig);
+
+    llfn
+}
+
+/// Plain functions are named by their path, while shims fall back to their mangled symbol name
+/// since they share the path of the item they are generated for.
+pub(crate) fn instance_name<'tcx>(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>) -> String {
+    match instance.def {
+        InstanceDef::Item(def) if !tcx.is_foreign_item(def.did) => {
+            tcx.def_path_str_with_substs(def.did, instance.substs)
+        }
+        _ => tcx.symbol_name(instance).name.to_string(),
+    }
+}
+
+pub unsafe fn optimize_fn<'tcx>(llmod: LLVMModuleRef, sess: &Session) -> LLVMExecutionEngineRef {
     let mut ee = std::mem::MaybeUninit::uninit();
     let mut out = std::mem::MaybeUninit::zeroed();
 
@@ -69,42 +147,98 @@ pub unsafe fn optimize_fn<'tcx>(llmod: LLVMModuleRef) -> LLVMExecutionEngineRef
     LLVM_InitializeNativeTarget();
     LLVM_InitializeNativeAsmPrinter();
 
-    LLVMCreateExecutionEngineForModule(ee.as_mut_ptr(), llmod, out.as_mut_ptr());
+    register_symbols();
+    emulate_thread_locals(llmod, sess);
+    for symbol in unresolved_symbols(llmod) {
+        sess.err(&format!("cannot resolve `{}` for the JIT", symbol));
+    }
+    sess.abort_if_errors();
 
-    let pm = LLVMCreatePassManager();
-    LLVMAddScalarReplAggregatesPass(pm);
+    if LLVMCreateExecutionEngineForModule(ee.as_mut_ptr(), llmod, out.as_mut_ptr()) != 0 {
+        let out = CStr::from_ptr(out.assume_init());
+        sess.fatal(&format!(
+            "failed to create the execution engine: {}",
+            out.to_string_lossy()
+        ));
+    }
 
-    LLVMRunPassManager(pm, llmod);
+    run_passes(llmod, sess);
 
     ee.assume_init()
 }
 
-pub unsafe fn execute_fn<'tcx>(ee: LLVMExecutionEngineRef, fn_name: CString) {
+/// Runs `fn_name`, which takes no arguments, and prints the value of type `ret_ty` it returns.
+pub unsafe fn execute_fn<'tcx>(ee: LLVMExecutionEngineRef, fn_name: CString, ret_ty: Ty<'tcx>) {
     let addr = LLVMGetFunctionAddress(ee, fn_name.as_ptr());
 
-    let f: extern "C" fn(()) -> () = std::mem::transmute(addr);
-    let ret = f(());
-    dbg!(ret);
+    macro_rules! call {
+        ($ty:ty) => {{
+            let f: extern "C" fn() -> $ty = std::mem::transmute(addr);
+            let ret = f();
+            dbg!(ret);
+        }};
+    }
+    match ret_ty.kind() {
+        TyKind::Tuple(fields) if fields.is_empty() => call!(()),
+        TyKind::Bool => call!(bool),
+        TyKind::Char => call!(char),
+        TyKind::Int(IntTy::Isize) => call!(isize),
+        TyKind::Int(IntTy::I8) => call!(i8),
+        TyKind::Int(IntTy::I16) => call!(i16),
+        TyKind::Int(IntTy::I32) => call!(i32),
+        TyKind::Int(IntTy::I64) => call!(i64),
+        TyKind::Int(IntTy::I128) => call!(i128),
+        TyKind::Uint(UintTy::Usize) => call!(usize),
+        TyKind::Uint(UintTy::U8) => call!(u8),
+        TyKind::Uint(UintTy::U16) => call!(u16),
+        TyKind::Uint(UintTy::U32) => call!(u32),
+        TyKind::Uint(UintTy::U64) => call!(u64),
+        TyKind::Uint(UintTy::U128) => call!(u128),
+        TyKind::Float(FloatTy::F32) => call!(f32),
+        TyKind::Float(FloatTy::F64) => call!(f64),
+        _ => todo!("executing a function returning `{}`", ret_ty),
+    }
 }
 
 impl<'tcx> FunctionCx<'tcx> {
     unsafe fn codegen_header(&mut self) {
         for local_decl in self.mir.local_decls.iter() {
-            self.locals.push(TPlace {
-                ty_and_layout: self
-                    .tcx
-                    .layout_of(ParamEnv::reveal_all().and(local_decl.ty))
-                    .unwrap(),
-                llval: OnceCell::new(),
-            });
+            let ty = self.monomorphize(local_decl.ty);
+            self.locals.push(TPlace::new(self.layout_of(ty)));
         }
+        let mut llidx = 0;
+        let mut spread_params = Vec::new();
         for (idx, arg_local) in self.mir.args_iter().enumerate() {
-            let param = LLVMGetParam(self.llfn, idx as core::ffi::c_uint);
-            let param_name = &self.mir.var_debug_info[idx].name;
-            let param_name = c_string!(param_name.to_ident_string());
-            LLVMSetValueName2(param, param_name.as_ptr(), param_name.as_bytes().len());
+            let layout = self.locals[arg_local].ty_and_layout;
+            if layout.is_zst() {
+                continue;
+            }
+            // Shims of "rust-call" functions receive their tupled arguments one field at a time,
+            // and put them back together in the stack slot of the tuple.
+            if Some(arg_local) == self.mir.spread_arg {
+                for field in 0..layout.fields.count() {
+                    if !layout.field(&layout_cx(self.tcx), field).is_zst() {
+                        spread_params.push((field, LLVMGetParam(self.llfn, llidx)));
+                        llidx += 1;
+                    }
+                }
+                continue;
+            }
+            let param = LLVMGetParam(self.llfn, llidx);
+            llidx += 1;
+            // Shims have no debug info to name their parameters after.
+            if let Some(var_debug_info) = self.mir.var_debug_info.get(idx) {
+                let param_name = c_string!(var_debug_info.name.to_ident_string());
+                LLVMSetValueName2(param, param_name.as_ptr(), param_name.as_bytes().len());
+            }
 
-            self.locals[arg_local].llval.set(param).unwrap();
+            self.locals[arg_local] = TPlace::new_immediate(layout, param);
+        }
+        if self.instance.def.requires_caller_location(self.tcx) {
+            let param = LLVMGetParam(self.llfn, llidx);
+            let param_name = c_string!("caller_location");
+            LLVMSetValueName2(param, param_name.as_ptr(), param_name.as_bytes().len());
+            self.caller_location = Some(param);
         }
 
         LLVMAppendBasicBlockInContext(self.llcx, self.llfn, c_string!("entry").as_ptr());
@@ -117,38 +251,56 @@ impl<'tcx> FunctionCx<'tcx> {
             self.basic_blocks.push(block);
         }
 
-        self.codegen_header_allocas();
+        self.codegen_header_allocas(spread_params);
     }
 
-    unsafe fn alloca(&mut self, local: mir::Local) -> Option<LLVMValueRef> {
+    unsafe fn alloca(&mut self, local: mir::Local) -> LLVMValueRef {
         let place = &self.locals[local];
-        if place.ty_and_layout.is_zst() {
-            None
-        } else {
-            Some(LLVMBuildAlloca(
-                self.llbx,
-                ty_to_llvm_type(self.llcx, place.ty_and_layout.ty),
-                c_string!("_", local.index()).as_ptr(),
-            ))
-        }
+        let alloca = LLVMBuildAlloca(
+            self.llbx,
+            ty_to_llvm_type(self.llcx, self.tcx, place.ty()),
+            c_string!("_", local.index()).as_ptr(),
+        );
+        // The LLVM type may be less aligned than the layout, e.g. for `#[repr(align(N))]`.
+        LLVMSetAlignment(alloca, place.ty_and_layout.align.abi.bytes() as _);
+        alloca
     }
 
-    unsafe fn codegen_header_allocas(&mut self) {
+    /// Gives the locals that need memory a stack slot. Arguments among them are spilled to it,
+    /// including the fields of a spread argument in `spread_params`. The other locals become SSA
+    /// values once they are assigned.
+    unsafe fn codegen_header_allocas(&mut self, spread_params: Vec<(usize, LLVMValueRef)>) {
         let entry = LLVMGetEntryBasicBlock(self.llfn);
         LLVMPositionBuilderAtEnd(self.llbx, entry);
 
-        for local in Some(mir::RETURN_PLACE)
-            .into_iter()
-            .chain(self.mir.local_decls.indices().skip(1 + self.mir.arg_count))
-        {
-            if let Some(alloca) = self.alloca(local) {
-                self.locals[local].llval.set(alloca).unwrap();
+        let non_ssa_locals = non_ssa_locals(self);
+        for local in self.mir.local_decls.indices() {
+            let layout = self.locals[local].ty_and_layout;
+            let is_arg = self.mir.local_kind(local) == LocalKind::Arg;
+            if non_ssa_locals.contains(local) {
+                let place = TPlace::new_ref(layout, self.alloca(local));
+                if Some(local) == self.mir.spread_arg {
+                    for &(field, param) in &spread_params {
+                        self.project_field(place.clone(), field)
+                            .store_llval(self.llbx, param);
+                    }
+                } else if is_arg {
+                    place.store_llval(self.llbx, *self.locals[local].llval());
+                }
+                self.locals[local] = place;
+            } else if !is_arg && !layout.is_zst() {
+                self.locals[local] = TPlace {
+                    immediate: true,
+                    ..TPlace::new(layout)
+                };
             }
         }
         LLVMBuildBr(self.llbx, LLVMGetNextBasicBlock(entry));
     }
 
     unsafe fn codegen_body(&mut self) {
+        self.cold_blocks = cold_blocks(self);
+        self.expected_values = expected_values(self);
         for (bb, data) in traversal::reverse_postorder(self.mir) {
             debug!("entering bb{}", bb.index());
             LLVMPositionBuilderAtEnd(self.llbx, self.basic_blocks[bb]);
@@ -165,69 +317,40 @@ impl<'tcx> FunctionCx<'tcx> {
         debug!(?stmt.kind);
         match &stmt.kind {
             StatementKind::Assign(box (place, rvalue)) => {
+                let dest = self.codegen_place(place);
                 let name = c_string!("");
                 match rvalue {
                     Rvalue::Use(operand) => {
-                        self.codegen_operand(operand)
-                            .load_scalar(self.llbx)
-                            .store(self.llbx, *self.locals[place.local].llval());
+                        let operand = self.codegen_operand(operand);
+                        operand.load_scalar(self.llbx).store(self.llbx, &dest);
+                    }
+                    Rvalue::CopyForDeref(place) => {
+                        let operand = self.codegen_place(place);
+                        operand.load_scalar(self.llbx).store(self.llbx, &dest);
                     }
                     Rvalue::BinaryOp(bin_op, box (lhs, rhs)) => {
-                        let lhs_ty = lhs.ty(&self.mir.local_decls, self.tcx);
-                        let _rhs_ty = rhs.ty(&self.mir.local_decls, self.tcx);
+                        let lhs_ty = self.monomorphize(lhs.ty(&self.mir.local_decls, self.tcx));
                         let lhs_val = *self.codegen_operand(lhs).load_scalar(self.llbx).llval();
                         let rhs_val = *self.codegen_operand(rhs).load_scalar(self.llbx).llval();
+                        let llval = self.codegen_scalar_binop(*bin_op, lhs_val, rhs_val, lhs_ty);
 
-                        let tmp = match bin_op {
-                            BinOp::Add if lhs_ty.is_integral() => {
-                                LLVMBuildAdd(self.llbx, lhs_val, rhs_val, name.as_ptr())
-                            }
-                            BinOp::Sub if lhs_ty.is_integral() => {
-                                LLVMBuildSub(self.llbx, lhs_val, rhs_val, name.as_ptr())
-                            }
-                            BinOp::Eq if lhs_ty.is_integral() => LLVMBuildICmp(
-                                self.llbx,
-                                llvm_sys::LLVMIntPredicate::LLVMIntEQ,
-                                lhs_val,
-                                rhs_val,
-                                name.as_ptr(),
-                            ),
-                            BinOp::Lt if lhs_ty.is_integral() && lhs_ty.is_signed() => {
-                                LLVMBuildICmp(
-                                    self.llbx,
-                                    llvm_sys::LLVMIntPredicate::LLVMIntSLT,
-                                    lhs_val,
-                                    rhs_val,
-                                    name.as_ptr(),
-                                )
-                            }
-                            BinOp::Lt if lhs_ty.is_integral() => LLVMBuildICmp(
-                                self.llbx,
-                                llvm_sys::LLVMIntPredicate::LLVMIntULT,
-                                lhs_val,
-                                rhs_val,
-                                name.as_ptr(),
-                            ),
-                            BinOp::Le if lhs_ty.is_integral() && lhs_ty.is_signed() => {
-                                LLVMBuildICmp(
-                                    self.llbx,
-                                    llvm_sys::LLVMIntPredicate::LLVMIntSLE,
-                                    lhs_val,
-                                    rhs_val,
-                                    name.as_ptr(),
-                                )
-                            }
-                            BinOp::Le if lhs_ty.is_integral() => LLVMBuildICmp(
-                                self.llbx,
-                                llvm_sys::LLVMIntPredicate::LLVMIntULE,
-                                lhs_val,
-                                rhs_val,
-                                name.as_ptr(),
-                            ),
-                            _ => todo!(),
+                        dest.store_llval(self.llbx, llval);
+                    }
+                    Rvalue::CheckedBinaryOp(bin_op, box (lhs, rhs)) => {
+                        let lhs_ty = self.monomorphize(lhs.ty(&self.mir.local_decls, self.tcx));
+                        let lhs_val = *self.codegen_operand(lhs).load_scalar(self.llbx).llval();
+                        let rhs_val = *self.codegen_operand(rhs).load_scalar(self.llbx).llval();
+                        // Bodies with `#[rustc_inherit_overflow_checks]`, like `<u32 as Add>::add`,
+                        // are checked in their MIR, but only overflow if the crate checks for it.
+                        let (llval, overflow) = if self.tcx.sess.overflow_checks() {
+                            self.codegen_checked_binop(*bin_op, lhs_val, rhs_val, lhs_ty)
+                        } else {
+                            let llval =
+                                self.codegen_scalar_binop(*bin_op, lhs_val, rhs_val, lhs_ty);
+                            (llval, LLVMConstInt(LLVMInt1TypeInContext(self.llcx), 0, 0))
                         };
 
-                        LLVMBuildStore(self.llbx, tmp, *self.locals[place.local].llval());
+                        self.store_pair(dest, llval, overflow);
                     }
                     Rvalue::UnaryOp(un_op, operand) => {
                         let operand = self.codegen_operand(operand).load_scalar(self.llbx);
@@ -244,68 +367,1159 @@ impl<'tcx> FunctionCx<'tcx> {
                             }
                         };
 
-                        LLVMBuildStore(self.llbx, llval, *self.locals[place.local].llval());
+                        dest.store_llval(self.llbx, llval);
+                    }
+                    Rvalue::Ref(_, _, referent) | Rvalue::AddressOf(_, referent) => {
+                        let referent = self.codegen_place(referent);
+                        let llval = self.place_to_pointer(&referent, dest.ty());
+
+                        dest.store_llval(self.llbx, llval);
+                    }
+                    Rvalue::Cast(cast_kind, operand, cast_ty) => {
+                        let cast_ty = self.monomorphize(*cast_ty);
+                        let operand = self.codegen_operand(operand).load_scalar(self.llbx);
+                        let llval = match cast_kind {
+                            CastKind::Pointer(PointerCast::Unsize) => {
+                                self.codegen_unsize(operand, cast_ty)
+                            }
+                            CastKind::Pointer(PointerCast::ReifyFnPointer) => {
+                                let (def_id, substs) = match operand.ty().kind() {
+                                    TyKind::FnDef(def_id, substs) => (*def_id, *substs),
+                                    _ => unreachable!(),
+                                };
+                                let instance = Instance::resolve_for_fn_ptr(
+                                    self.tcx,
+                                    ParamEnv::reveal_all(),
+                                    def_id,
+                                    substs,
+                                )
+                                .unwrap();
+                                self.codegen_fn_pointer(instance, cast_ty)
+                            }
+                            // Only closures which capture nothing coerce to function pointers, so
+                            // their `FnOnce` shim ignores the environment.
+                            CastKind::Pointer(PointerCast::ClosureFnPointer(_)) => {
+                                let (def_id, substs) = match operand.ty().kind() {
+                                    TyKind::Closure(def_id, substs) => (*def_id, *substs),
+                                    _ => unreachable!(),
+                                };
+                                let instance = Instance::resolve_closure(
+                                    self.tcx,
+                                    def_id,
+                                    substs,
+                                    ClosureKind::FnOnce,
+                                );
+                                self.codegen_fn_pointer(instance, cast_ty)
+                            }
+                            CastKind::Pointer(PointerCast::UnsafeFnPointer) => *operand.llval(),
+                            CastKind::Pointer(
+                                PointerCast::MutToConstPointer | PointerCast::ArrayToPointer,
+                            )
+                            | CastKind::PtrToPtr
+                            | CastKind::FnPtrToPtr => self.codegen_ptr_to_ptr(operand, cast_ty),
+                            CastKind::PointerExposeAddress => {
+                                let mut llval = *operand.llval();
+                                // Function pointers are exposed too, and have no pointee.
+                                let pointee = operand.ty().builtin_deref(true);
+                                if pointee.map_or(false, |p| has_pointer_metadata(self.tcx, p.ty)) {
+                                    llval =
+                                        LLVMBuildExtractValue(self.llbx, llval, 0, name.as_ptr());
+                                }
+                                let cast_llty = ty_to_llvm_type(self.llcx, self.tcx, cast_ty);
+                                LLVMBuildPtrToInt(self.llbx, llval, cast_llty, name.as_ptr())
+                            }
+                            CastKind::PointerFromExposedAddress => {
+                                let cast_llty = ty_to_llvm_type(self.llcx, self.tcx, cast_ty);
+                                LLVMBuildIntToPtr(
+                                    self.llbx,
+                                    *operand.llval(),
+                                    cast_llty,
+                                    name.as_ptr(),
+                                )
+                            }
+                            CastKind::IntToInt
+                            | CastKind::IntToFloat
+                            | CastKind::FloatToInt
+                            | CastKind::FloatToFloat => self.codegen_numeric_cast(operand, cast_ty),
+                            CastKind::DynStar => todo!(),
+                        };
+
+                        dest.store_llval(self.llbx, llval);
+                    }
+                    Rvalue::Len(place) => {
+                        let place = self.codegen_place(place);
+                        let len = self.place_len(&place);
+
+                        dest.store_llval(self.llbx, len);
+                    }
+                    Rvalue::Repeat(operand, count) => {
+                        let count = self
+                            .monomorphize(*count)
+                            .eval_usize(self.tcx, ParamEnv::reveal_all());
+                        let operand = self.codegen_operand(operand).load_scalar(self.llbx);
+                        self.codegen_repeat(&dest, operand, count);
+                    }
+                    Rvalue::Aggregate(box AggregateKind::Array(_), operands) => {
+                        for (idx, operand) in operands.iter().enumerate() {
+                            let operand = self.codegen_operand(operand);
+                            if operand.ty_and_layout.is_zst() {
+                                continue;
+                            }
+
+                            let llidx =
+                                LLVMConstInt(isize_type(self.llcx, self.tcx), idx as u64, 0);
+                            let elem = self.project_index(dest.clone(), llidx);
+                            operand.load_scalar(self.llbx).store(self.llbx, &elem);
+                        }
+                    }
+                    Rvalue::NullaryOp(null_op, ty) => {
+                        let layout = self.layout_of(self.monomorphize(*ty));
+                        let val = match null_op {
+                            NullOp::SizeOf => layout.size.bytes(),
+                            NullOp::AlignOf => layout.align.abi.bytes(),
+                        };
+                        let llval = LLVMConstInt(isize_type(self.llcx, self.tcx), val, 0);
+
+                        dest.store_llval(self.llbx, llval);
+                    }
+                    // The allocation is only reinterpreted as a `Box` here. Its contents are
+                    // initialized through a deref of the `Box` afterwards.
+                    Rvalue::ShallowInitBox(operand, _) => {
+                        let operand = self.codegen_operand(operand).load_scalar(self.llbx);
+                        let llval = LLVMBuildBitCast(
+                            self.llbx,
+                            *operand.llval(),
+                            ty_to_llvm_type(self.llcx, self.tcx, dest.ty()),
+                            name.as_ptr(),
+                        );
+
+                        dest.store_llval(self.llbx, llval);
+                    }
+                    Rvalue::ThreadLocalRef(def_id) => {
+                        let global = get_static(self.llcx, self.llmod, self.tcx, *def_id);
+                        let llty = ty_to_llvm_type(self.llcx, self.tcx, dest.ty());
+                        let llval = LLVMConstBitCast(global, llty);
+
+                        dest.store_llval(self.llbx, llval);
+                    }
+                    Rvalue::Discriminant(place) => {
+                        let place = self.codegen_place(place);
+                        let llval = self.codegen_get_discr(place, dest.ty());
+
+                        dest.store_llval(self.llbx, llval);
                     }
                     _ => todo!(),
                 }
             }
-            StatementKind::StorageLive(_) | StatementKind::StorageDead(_) => {
-                warn!("codegen skipped for {:?}", stmt.kind);
+            StatementKind::SetDiscriminant {
+                place,
+                variant_index,
+            } => {
+                let place = self.codegen_place(place);
+                self.codegen_set_discr(place, *variant_index);
             }
-            _ => todo!(),
+            StatementKind::Intrinsic(box NonDivergingIntrinsic::Assume(operand)) => {
+                let cond = self.codegen_operand(operand).load_scalar(self.llbx);
+                self.call_intrinsic("llvm.assume", &[], &[*cond.llval()]);
+            }
+            StatementKind::Intrinsic(box NonDivergingIntrinsic::CopyNonOverlapping(
+                mir::CopyNonOverlapping { src, dst, count },
+            )) => {
+                let src_ty = self.monomorphize(src.ty(&self.mir.local_decls, self.tcx));
+                let ty = src_ty.builtin_deref(true).unwrap().ty;
+                let src = *self.codegen_operand(src).load_scalar(self.llbx).llval();
+                let dst = *self.codegen_operand(dst).load_scalar(self.llbx).llval();
+                let count = *self.codegen_operand(count).load_scalar(self.llbx).llval();
+                self.codegen_copy(ty, src, dst, count, true);
+            }
+            StatementKind::StorageLive(local) => {
+                self.codegen_lifetime_marker("llvm.lifetime.start", *local);
+            }
+            StatementKind::StorageDead(local) => {
+                self.codegen_lifetime_marker("llvm.lifetime.end", *local);
+            }
+            // The previous value of the place is gone, so its bytes become undefined.
+            StatementKind::Deinit(place) => {
+                let place = self.codegen_place(place);
+                let llty = ty_to_llvm_type(self.llcx, self.tcx, place.ty());
+                place.store_llval(self.llbx, LLVMGetUndef(llty));
+            }
+            // These only matter to borrowck, Miri and coverage instrumentation, which tachyon
+            // does not support.
+            StatementKind::FakeRead(..)
+            | StatementKind::Retag(..)
+            | StatementKind::AscribeUserType(..)
+            | StatementKind::Coverage(..)
+            | StatementKind::ConstEvalCounter
+            | StatementKind::Nop => {}
         }
     }
 
-    unsafe fn codegen_operand(&mut self, operand: &Operand<'tcx>) -> TPlace<'tcx> {
-        match operand {
-            mir::Operand::Copy(place) | mir::Operand::Move(place) => {
-                let layout = self.locals[place.local].ty_and_layout;
-                let llval = self.locals[place.local].llval.clone();
+    /// Marks the stack slot of `local` as live or dead, which lets LLVM share one slot between
+    /// locals that are never live at the same time. SSA and zero-sized locals have no slot.
+    unsafe fn codegen_lifetime_marker(&mut self, intrinsic: &str, local: mir::Local) {
+        let place = &self.locals[local];
+        if place.immediate || place.ty_and_layout.is_zst() {
+            return;
+        }
+
+        let i8p = i8p_type(self.llcx);
+        let size = place.ty_and_layout.size.bytes();
+        let size = LLVMConstInt(LLVMInt64TypeInContext(self.llcx), size, 0);
+        let ptr = LLVMBuildBitCast(self.llbx, *place.llval(), i8p, c_string!("").as_ptr());
+        self.call_intrinsic(intrinsic, &[i8p], &[size, ptr]);
+    }
 
-                TPlace {
-                    ty_and_layout: layout,
-                    llval,
+    pub(crate) unsafe fn codegen_place(&mut self, place: &mir::Place<'tcx>) -> TPlace<'tcx> {
+        let mut cg_place = self.locals[place.local].clone();
+        for elem in place.projection {
+            cg_place = match elem {
+                ProjectionElem::Deref => self.codegen_deref(cg_place),
+                ProjectionElem::Field(field, _) => self.project_field(cg_place, field.index()),
+                ProjectionElem::Downcast(_, variant) => TPlace {
+                    ty_and_layout: cg_place
+                        .ty_and_layout
+                        .for_variant(&layout_cx(self.tcx), *variant),
+                    ..cg_place
+                },
+                ProjectionElem::Index(local) => {
+                    let idx = self.locals[*local].clone().load_scalar(self.llbx);
+                    self.project_index(cg_place, *idx.llval())
                 }
+                ProjectionElem::ConstantIndex {
+                    offset, from_end, ..
+                } => {
+                    let offset = LLVMConstInt(isize_type(self.llcx, self.tcx), *offset, 0);
+                    let idx = if *from_end {
+                        let len = self.place_len(&cg_place);
+                        LLVMBuildSub(self.llbx, len, offset, c_string!("").as_ptr())
+                    } else {
+                        offset
+                    };
+                    self.project_index(cg_place, idx)
+                }
+                ProjectionElem::Subslice { from, to, from_end } => {
+                    self.project_subslice(cg_place, *from, *to, *from_end)
+                }
+                _ => todo!(),
+            };
+        }
+
+        cg_place
+    }
+
+    /// Reads the discriminant of the enum `place` as a value of type `cast_to`.
+    unsafe fn codegen_get_discr(&mut self, place: TPlace<'tcx>, cast_to: Ty<'tcx>) -> LLVMValueRef {
+        let name = c_string!("");
+        let cast_llty = ty_to_llvm_type(self.llcx, self.tcx, cast_to);
+        let layout = place.ty_and_layout;
+        let (tag, tag_encoding, tag_field) = match &layout.variants {
+            Variants::Single { index } => {
+                let discr = layout
+                    .ty
+                    .discriminant_for_variant(self.tcx, *index)
+                    .map_or(index.as_u32() as u64, |discr| discr.val as u64);
+                return LLVMConstInt(cast_llty, discr, 0);
             }
-            mir::Operand::Constant(constant) => {
-                let layout = self
-                    .tcx
-                    .layout_of(ParamEnv::reveal_all().and(constant.ty()))
-                    .unwrap();
-                let llval = match constant.literal {
-                    mir::ConstantKind::Val(val, ty) => match val {
-                        ConstValue::Scalar(rustc_const_eval::interpret::Scalar::Int(int)) => {
-                            let val = int.to_bits(int.size()).unwrap() as u64;
-                            let int_type = match int.size().bits() {
-                                8 if ty.is_bool() => LLVMInt1TypeInContext(self.llcx),
-                                8 => LLVMInt8TypeInContext(self.llcx),
-                                16 => LLVMInt16TypeInContext(self.llcx),
-                                32 => LLVMInt32TypeInContext(self.llcx),
-                                64 => LLVMInt128TypeInContext(self.llcx),
-                                128 => todo!(),
-                                _ => unreachable!(),
-                            };
-                            LLVMConstInt(int_type, val, 0)
-                        }
-                        _ => todo!(),
-                    },
-                    _ => todo!(),
+            Variants::Multiple {
+                tag,
+                tag_encoding,
+                tag_field,
+                ..
+            } => (tag, tag_encoding, *tag_field),
+        };
+
+        let tag_place = self.project_field(place, tag_field);
+        let mut tag_val = *tag_place.load_scalar(self.llbx).llval();
+        // The field of the tag has the full range of its type, unlike the tag itself.
+        if !LLVMIsALoadInst(tag_val).is_null() {
+            apply_range_metadata(tag_val, *tag);
+        }
+        let is_signed = match tag.primitive() {
+            Primitive::Int(_, is_signed) => is_signed,
+            Primitive::Pointer => {
+                let usize_llty = isize_type(self.llcx, self.tcx);
+                tag_val = LLVMBuildPtrToInt(self.llbx, tag_val, usize_llty, name.as_ptr());
+                false
+            }
+            _ => false,
+        };
+
+        match tag_encoding {
+            TagEncoding::Direct => {
+                LLVMBuildIntCast2(self.llbx, tag_val, cast_llty, is_signed as _, name.as_ptr())
+            }
+            // Variants other than the dataful one are encoded as values of the niche, offset by
+            // `niche_start`, in the order of their indices.
+            TagEncoding::Niche {
+                dataful_variant,
+                niche_variants,
+                niche_start,
+            } => {
+                let tag_llty = LLVMTypeOf(tag_val);
+                let relative_discr = LLVMBuildSub(
+                    self.llbx,
+                    tag_val,
+                    LLVMConstInt(tag_llty, *niche_start as u64, 0),
+                    name.as_ptr(),
+                );
+                let relative_max = niche_variants.end().as_u32() - niche_variants.start().as_u32();
+                let is_niche = LLVMBuildICmp(
+                    self.llbx,
+                    llvm_sys::LLVMIntPredicate::LLVMIntULE,
+                    relative_discr,
+                    LLVMConstInt(tag_llty, relative_max as u64, 0),
+                    name.as_ptr(),
+                );
+                let niche_discr =
+                    LLVMBuildIntCast2(self.llbx, relative_discr, cast_llty, 0, name.as_ptr());
+                let niche_discr = LLVMBuildAdd(
+                    self.llbx,
+                    niche_discr,
+                    LLVMConstInt(cast_llty, niche_variants.start().as_u32() as u64, 0),
+                    name.as_ptr(),
+                );
+                LLVMBuildSelect(
+                    self.llbx,
+                    is_niche,
+                    niche_discr,
+                    LLVMConstInt(cast_llty, dataful_variant.as_u32() as u64, 0),
+                    name.as_ptr(),
+                )
+            }
+        }
+    }
+
+    /// Writes the tag of `variant_index` into the enum `place`.
+    unsafe fn codegen_set_discr(&mut self, place: TPlace<'tcx>, variant_index: VariantIdx) {
+        let layout = place.ty_and_layout;
+        if layout
+            .for_variant(&layout_cx(self.tcx), variant_index)
+            .abi
+            .is_uninhabited()
+        {
+            return;
+        }
+        let (tag_encoding, tag_field) = match &layout.variants {
+            Variants::Single { index } => {
+                assert_eq!(*index, variant_index);
+                return;
+            }
+            Variants::Multiple {
+                tag_encoding,
+                tag_field,
+                ..
+            } => (tag_encoding, *tag_field),
+        };
+
+        let tag_val = match tag_encoding {
+            TagEncoding::Direct => {
+                layout
+                    .ty
+                    .discriminant_for_variant(self.tcx, variant_index)
+                    .unwrap()
+                    .val
+            }
+            // The dataful variant has no tag, its data occupies the niche instead.
+            TagEncoding::Niche {
+                dataful_variant, ..
+            } if *dataful_variant == variant_index => return,
+            TagEncoding::Niche {
+                niche_variants,
+                niche_start,
+                ..
+            } => ((variant_index.as_u32() - niche_variants.start().as_u32()) as u128)
+                .wrapping_add(*niche_start),
+        };
+
+        let tag_place = self.project_field(place, tag_field);
+        let tag_llty = ty_to_llvm_type(self.llcx, self.tcx, tag_place.ty());
+        let words = [tag_val as u64, (tag_val >> 64) as u64];
+        let tag_llval = match LLVMGetTypeKind(tag_llty) {
+            LLVMTypeKind::LLVMPointerTypeKind => LLVMConstIntToPtr(
+                LLVMConstInt(isize_type(self.llcx, self.tcx), tag_val as u64, 0),
+                tag_llty,
+            ),
+            _ => LLVMConstIntOfArbitraryPrecision(tag_llty, 2, words.as_ptr()),
+        };
+
+        tag_place.store_llval(self.llbx, tag_llval);
+    }
+
+    /// Lowers `lhs bin_op rhs`, where `ty` is the type of `lhs`.
+    pub(crate) unsafe fn codegen_scalar_binop(
+        &mut self,
+        bin_op: BinOp,
+        lhs: LLVMValueRef,
+        rhs: LLVMValueRef,
+        ty: Ty<'tcx>,
+    ) -> LLVMValueRef {
+        use llvm_sys::{LLVMIntPredicate::*, LLVMRealPredicate::*};
+
+        let name = c_string!("");
+        let is_float = ty.is_floating_point();
+        let is_signed = ty.is_signed();
+        match bin_op {
+            BinOp::Add if is_float => LLVMBuildFAdd(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Add => LLVMBuildAdd(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Sub if is_float => LLVMBuildFSub(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Sub => LLVMBuildSub(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Mul if is_float => LLVMBuildFMul(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Mul => LLVMBuildMul(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Div if is_float => LLVMBuildFDiv(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Div if is_signed => LLVMBuildSDiv(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Div => LLVMBuildUDiv(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Rem if is_float => LLVMBuildFRem(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Rem if is_signed => LLVMBuildSRem(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Rem => LLVMBuildURem(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::BitXor => LLVMBuildXor(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::BitAnd => LLVMBuildAnd(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::BitOr => LLVMBuildOr(self.llbx, lhs, rhs, name.as_ptr()),
+            BinOp::Shl => {
+                let rhs = self.shift_amount(lhs, rhs);
+                LLVMBuildShl(self.llbx, lhs, rhs, name.as_ptr())
+            }
+            BinOp::Shr if is_signed => {
+                let rhs = self.shift_amount(lhs, rhs);
+                LLVMBuildAShr(self.llbx, lhs, rhs, name.as_ptr())
+            }
+            BinOp::Shr => {
+                let rhs = self.shift_amount(lhs, rhs);
+                LLVMBuildLShr(self.llbx, lhs, rhs, name.as_ptr())
+            }
+            BinOp::Offset => {
+                let pointee = self.layout_of(ty.builtin_deref(true).unwrap().ty);
+                if pointee.is_zst() {
+                    return lhs;
+                }
+                let mut indices = [rhs];
+                LLVMBuildInBoundsGEP2(
+                    self.llbx,
+                    ty_to_llvm_type(self.llcx, self.tcx, pointee.ty),
+                    lhs,
+                    indices.as_mut_ptr(),
+                    indices.len() as core::ffi::c_uint,
+                    name.as_ptr(),
+                )
+            }
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                let (int_pred, signed_pred, real_pred) = match bin_op {
+                    BinOp::Eq => (LLVMIntEQ, LLVMIntEQ, LLVMRealOEQ),
+                    BinOp::Ne => (LLVMIntNE, LLVMIntNE, LLVMRealUNE),
+                    BinOp::Lt => (LLVMIntULT, LLVMIntSLT, LLVMRealOLT),
+                    BinOp::Le => (LLVMIntULE, LLVMIntSLE, LLVMRealOLE),
+                    BinOp::Gt => (LLVMIntUGT, LLVMIntSGT, LLVMRealOGT),
+                    BinOp::Ge => (LLVMIntUGE, LLVMIntSGE, LLVMRealOGE),
+                    _ => unreachable!(),
                 };
+                if is_float {
+                    LLVMBuildFCmp(self.llbx, real_pred, lhs, rhs, name.as_ptr())
+                } else if is_signed {
+                    LLVMBuildICmp(self.llbx, signed_pred, lhs, rhs, name.as_ptr())
+                } else {
+                    LLVMBuildICmp(self.llbx, int_pred, lhs, rhs, name.as_ptr())
+                }
+            }
+        }
+    }
+
+    /// Lowers `lhs bin_op rhs` along with whether it overflowed, where `ty` is the type of `lhs`.
+    pub(crate) unsafe fn codegen_checked_binop(
+        &mut self,
+        bin_op: BinOp,
+        lhs: LLVMValueRef,
+        rhs: LLVMValueRef,
+        ty: Ty<'tcx>,
+    ) -> (LLVMValueRef, LLVMValueRef) {
+        let name = c_string!("");
+        let op = match bin_op {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            // Shifts overflow when the amount is at least the bit width of `lhs`.
+            BinOp::Shl | BinOp::Shr => {
+                let llval = self.codegen_scalar_binop(bin_op, lhs, rhs, ty);
+                let bits = LLVMGetIntTypeWidth(LLVMTypeOf(lhs));
+                let overflow = LLVMBuildICmp(
+                    self.llbx,
+                    llvm_sys::LLVMIntPredicate::LLVMIntUGE,
+                    rhs,
+                    LLVMConstInt(LLVMTypeOf(rhs), bits as u64, 0),
+                    name.as_ptr(),
+                );
+                return (llval, overflow);
+            }
+            _ => unreachable!("{:?} cannot overflow", bin_op),
+        };
+
+        let sign = if ty.is_signed() { "s" } else { "u" };
+        let intrinsic = format!("llvm.{}{}.with.overflow", sign, op);
+        let pair = self.call_intrinsic(&intrinsic, &[LLVMTypeOf(lhs)], &[lhs, rhs]);
+        (
+            LLVMBuildExtractValue(self.llbx, pair, 0, name.as_ptr()),
+            LLVMBuildExtractValue(self.llbx, pair, 1, name.as_ptr()),
+        )
+    }
+
+    /// Converts the shift amount `rhs` to the type of `lhs`, masked to its bit width like rustc
+    /// does, since LLVM shifts by the bit width or more are poison.
+    unsafe fn shift_amount(&mut self, lhs: LLVMValueRef, rhs: LLVMValueRef) -> LLVMValueRef {
+        let name = c_string!("");
+        let llty = LLVMTypeOf(lhs);
+        let rhs = LLVMBuildIntCast2(self.llbx, rhs, llty, 0, name.as_ptr());
+        let mask = LLVMConstInt(llty, LLVMGetIntTypeWidth(llty) as u64 - 1, 0);
+        LLVMBuildAnd(self.llbx, rhs, mask, name.as_ptr())
+    }
+
+    /// Stores `a` and `b` into the first two fields of `dest`, e.g. a result and its overflow flag.
+    pub(crate) unsafe fn store_pair(
+        &mut self,
+        dest: TPlace<'tcx>,
+        a: LLVMValueRef,
+        b: LLVMValueRef,
+    ) {
+        let first = self.project_field(dest.clone(), 0);
+        first.store_llval(self.llbx, a);
+        let second = self.project_field(dest, 1);
+        second.store_llval(self.llbx, b);
+    }
+
+    /// Returns the `llidx`-th element of an array or slice place.
+    unsafe fn project_index(&mut self, base: TPlace<'tcx>, llidx: LLVMValueRef) -> TPlace<'tcx> {
+        let elem = base.ty_and_layout.field(&layout_cx(self.tcx), 0);
+        if elem.is_zst() {
+            return TPlace::new(elem);
+        }
+
+        let name = c_string!("");
+        let elem_llty = ty_to_llvm_type(self.llcx, self.tcx, elem.ty);
+        let ptr = LLVMBuildBitCast(
+            self.llbx,
+            *base.llval(),
+            LLVMPointerType(elem_llty, 0),
+            name.as_ptr(),
+        );
+        let mut indices = [llidx];
+        let ptr = LLVMBuildInBoundsGEP2(
+            self.llbx,
+            elem_llty,
+            ptr,
+            indices.as_mut_ptr(),
+            indices.len() as core::ffi::c_uint,
+            name.as_ptr(),
+        );
+
+        TPlace {
+            align: base.align.restrict_for_offset(elem.size),
+            ..TPlace::new_ref(elem, ptr)
+        }
+    }
+
+    /// Returns the elements `from..len - to` of a slice place if `from_end` is set, and the
+    /// elements `from..to` of an array place otherwise.
+    unsafe fn project_subslice(
+        &mut self,
+        base: TPlace<'tcx>,
+        from: u64,
+        to: u64,
+        from_end: bool,
+    ) -> TPlace<'tcx> {
+        let usize_llty = isize_type(self.llcx, self.tcx);
+        let (layout, llextra) = if from_end {
+            let len = self.place_len(&base);
+            let len = LLVMBuildSub(
+                self.llbx,
+                len,
+                LLVMConstInt(usize_llty, from + to, 0),
+                c_string!("").as_ptr(),
+            );
+            (base.ty_and_layout, Some(len))
+        } else {
+            let elem = base.ty_and_layout.field(&layout_cx(self.tcx), 0);
+            (self.layout_of(self.tcx.mk_array(elem.ty, to - from)), None)
+        };
+
+        let elem = self.project_index(base, LLVMConstInt(usize_llty, from, 0));
+        let mut subslice = TPlace {
+            llextra,
+            align: elem.align,
+            ..TPlace::new(layout)
+        };
+        if let Some(ptr) = elem.llval.get() {
+            let ptr = match llextra {
+                Some(_) => *ptr,
+                None => LLVMBuildBitCast(
+                    self.llbx,
+                    *ptr,
+                    LLVMPointerType(ty_to_llvm_type(self.llcx, self.tcx, layout.ty), 0),
+                    c_string!("").as_ptr(),
+                ),
+            };
+            subslice.llval.set(ptr).unwrap();
+        }
+
+        subslice
+    }
+
+    /// Returns the number of elements of an array or slice place as a `usize`.
+    unsafe fn place_len(&mut self, place: &TPlace<'tcx>) -> LLVMValueRef {
+        match place.ty().kind() {
+            TyKind::Array(_, len) => LLVMConstInt(
+                isize_type(self.llcx, self.tcx),
+                len.eval_usize(self.tcx, ParamEnv::reveal_all()),
+                0,
+            ),
+            TyKind::Slice(_) | TyKind::Str => place.llextra.unwrap(),
+            _ => todo!(),
+        }
+    }
+
+    /// Computes the size and alignment of a value of type `layout`, taking the metadata
+    /// `llextra` into account for unsized types.
+    pub(crate) unsafe fn size_and_align_of(
+        &mut self,
+        layout: TyAndLayout<'tcx>,
+        llextra: Option<LLVMValueRef>,
+    ) -> (LLVMValueRef, LLVMValueRef) {
+        let name = c_string!("");
+        let usize_llty = isize_type(self.llcx, self.tcx);
+        if layout.is_sized() {
+            return (
+                LLVMConstInt(usize_llty, layout.size.bytes(), 0),
+                LLVMConstInt(usize_llty, layout.align.abi.bytes(), 0),
+            );
+        }
+
+        match layout.ty.kind() {
+            TyKind::Dynamic(..) => {
+                let vtable = llextra.unwrap();
+                let size = load_vtable_entry(self.llcx, self.llbx, vtable, SIZE);
+                let align = load_vtable_entry(self.llcx, self.llbx, vtable, ALIGN);
+                (
+                    LLVMBuildPtrToInt(self.llbx, size, usize_llty, name.as_ptr()),
+                    LLVMBuildPtrToInt(self.llbx, align, usize_llty, name.as_ptr()),
+                )
+            }
+            TyKind::Slice(_) | TyKind::Str => {
+                let elem = layout.field(&layout_cx(self.tcx), 0);
+                let size = LLVMBuildMul(
+                    self.llbx,
+                    llextra.unwrap(),
+                    LLVMConstInt(usize_llty, elem.size.bytes(), 0),
+                    name.as_ptr(),
+                );
+                (size, LLVMConstInt(usize_llty, elem.align.abi.bytes(), 0))
+            }
+            // A struct whose last field is unsized.
+            _ => {
+                let idx = layout.fields.count() - 1;
+                let sized_size = LLVMConstInt(usize_llty, layout.fields.offset(idx).bytes(), 0);
+                let sized_align = LLVMConstInt(usize_llty, layout.align.abi.bytes(), 0);
+                let field = layout.field(&layout_cx(self.tcx), idx);
+                let (unsized_size, unsized_align) = self.size_and_align_of(field, llextra);
+
+                let size = LLVMBuildAdd(self.llbx, sized_size, unsized_size, name.as_ptr());
+                let is_unsized_align_greater = LLVMBuildICmp(
+                    self.llbx,
+                    llvm_sys::LLVMIntPredicate::LLVMIntUGT,
+                    unsized_align,
+                    sized_align,
+                    name.as_ptr(),
+                );
+                let align = LLVMBuildSelect(
+                    self.llbx,
+                    is_unsized_align_greater,
+                    unsized_align,
+                    sized_align,
+                    name.as_ptr(),
+                );
 
-                TPlace {
-                    ty_and_layout: layout,
-                    llval: OnceCell::from(llval),
+                (self.round_up_to_alignment(size, align), align)
+            }
+        }
+    }
+
+    /// Rounds `value` up to a multiple of `align`, which is a power of two.
+    unsafe fn round_up_to_alignment(
+        &mut self,
+        value: LLVMValueRef,
+        align: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let name = c_string!("");
+        let one = LLVMConstInt(LLVMTypeOf(align), 1, 0);
+        let mask = LLVMBuildSub(self.llbx, align, one, name.as_ptr());
+        let value = LLVMBuildAdd(self.llbx, value, mask, name.as_ptr());
+        LLVMBuildAnd(
+            self.llbx,
+            value,
+            LLVMBuildNot(self.llbx, mask, name.as_ptr()),
+            name.as_ptr(),
+        )
+    }
+
+    /// Fills the array `dest` with `count` copies of `operand`. Elements whose bytes are all the
+    /// same become a `memset`, anything else is stored in a loop.
+    unsafe fn codegen_repeat(&mut self, dest: &TPlace<'tcx>, operand: TPlace<'tcx>, count: u64) {
+        let elem = operand.ty_and_layout;
+        if count == 0 || elem.is_zst() {
+            return;
+        }
+
+        let name = c_string!("");
+        let usize_llty = isize_type(self.llcx, self.tcx);
+        let llval = *operand.llval();
+        if let Some(byte) = splat_byte(llval, elem.size.bytes()) {
+            let ptr =
+                LLVMBuildBitCast(self.llbx, *dest.llval(), i8p_type(self.llcx), name.as_ptr());
+            LLVMBuildMemSet(
+                self.llbx,
+                ptr,
+                LLVMConstInt(LLVMInt8TypeInContext(self.llcx), byte as u64, 0),
+                LLVMConstInt(usize_llty, elem.size.bytes() * count, 0),
+                elem.align.abi.bytes() as core::ffi::c_uint,
+            );
+            return;
+        }
+
+        let elem_llty = LLVMTypeOf(llval);
+        let start = LLVMBuildBitCast(
+            self.llbx,
+            *dest.llval(),
+            LLVMPointerType(elem_llty, 0),
+            name.as_ptr(),
+        );
+        let mut indices = [LLVMConstInt(usize_llty, count, 0)];
+        let end = LLVMBuildInBoundsGEP2(
+            self.llbx,
+            elem_llty,
+            start,
+            indices.as_mut_ptr(),
+            indices.len() as core::ffi::c_uint,
+            name.as_ptr(),
+        );
+
+        let entry = LLVMGetInsertBlock(self.llbx);
+        let header = LLVMAppendBasicBlockInContext(
+            self.llcx,
+            self.llfn,
+            c_string!("repeat_header").as_ptr(),
+        );
+        let body =
+            LLVMAppendBasicBlockInContext(self.llcx, self.llfn, c_string!("repeat_body").as_ptr());
+        let next =
+            LLVMAppendBasicBlockInContext(self.llcx, self.llfn, c_string!("repeat_next").as_ptr());
+        LLVMBuildBr(self.llbx, header);
+
+        LLVMPositionBuilderAtEnd(self.llbx, header);
+        let current = LLVMBuildPhi(self.llbx, LLVMTypeOf(start), name.as_ptr());
+        let is_done = LLVMBuildICmp(
+            self.llbx,
+            llvm_sys::LLVMIntPredicate::LLVMIntEQ,
+            current,
+            end,
+            name.as_ptr(),
+        );
+        LLVMBuildCondBr(self.llbx, is_done, next, body);
+
+        LLVMPositionBuilderAtEnd(self.llbx, body);
+        LLVMBuildStore(self.llbx, llval, current);
+        let mut indices = [LLVMConstInt(usize_llty, 1, 0)];
+        let following = LLVMBuildInBoundsGEP2(
+            self.llbx,
+            elem_llty,
+            current,
+            indices.as_mut_ptr(),
+            indices.len() as core::ffi::c_uint,
+            name.as_ptr(),
+        );
+        LLVMBuildBr(self.llbx, header);
+
+        let mut incoming_values = [start, following];
+        let mut incoming_blocks = [entry, body];
+        LLVMAddIncoming(
+            current,
+            incoming_values.as_mut_ptr(),
+            incoming_blocks.as_mut_ptr(),
+            incoming_values.len() as core::ffi::c_uint,
+        );
+
+        LLVMPositionBuilderAtEnd(self.llbx, next);
+    }
+
+    /// Turns a pointer into the place it points to. Pointers to unsized places carry their
+    /// metadata along into `llextra`.
+    unsafe fn codegen_deref(&mut self, ptr: TPlace<'tcx>) -> TPlace<'tcx> {
+        let pointee = self.layout_of(ptr.ty().builtin_deref(true).unwrap().ty);
+        let ptr = ptr.load_scalar(self.llbx);
+        if !has_pointer_metadata(self.tcx, pointee.ty) {
+            return TPlace::new_ref(pointee, *ptr.llval());
+        }
+
+        let name = c_string!("");
+        let data = LLVMBuildExtractValue(self.llbx, *ptr.llval(), 0, name.as_ptr());
+        let meta = LLVMBuildExtractValue(self.llbx, *ptr.llval(), 1, name.as_ptr());
+        TPlace {
+            llextra: Some(meta),
+            ..TPlace::new_ref(pointee, data)
+        }
+    }
+
+    /// Fields are addressed by their byte offset from the layout, which keeps this independent
+    /// of how the parent type happens to be lowered.
+    pub(crate) unsafe fn project_field(&mut self, base: TPlace<'tcx>, idx: usize) -> TPlace<'tcx> {
+        let field = base.ty_and_layout.field(&layout_cx(self.tcx), idx);
+        if field.is_zst() {
+            return TPlace::new(field);
+        }
+        // Newtypes share the representation of their field, while the fields of scalar pairs are
+        // the elements of an LLVM struct.
+        if base.immediate {
+            let layout = base.ty_and_layout;
+            if !matches!(layout.abi, Abi::ScalarPair(..))
+                || newtype_field(self.tcx, layout).is_some()
+            {
+                return TPlace::new_immediate(field, *base.llval());
+            }
+            let llidx = match layout.ty.kind() {
+                TyKind::Ref(..) | TyKind::RawPtr(..) => idx as core::ffi::c_uint,
+                _ => llvm_field_index(self.tcx, layout, idx),
+            };
+            let llval =
+                LLVMBuildExtractValue(self.llbx, *base.llval(), llidx, c_string!("").as_ptr());
+            return TPlace::new_immediate(field, llval);
+        }
+
+        let name = c_string!("");
+        let offset = base.ty_and_layout.fields.offset(idx);
+        let mut ptr = *base.llval();
+        if offset.bytes() != 0 {
+            let mut lloffset = LLVMConstInt(isize_type(self.llcx, self.tcx), offset.bytes(), 0);
+            // The offset of an unsized tail like `dyn Trait` is only aligned at runtime, when the
+            // alignment of the tail is read from its vtable.
+            let is_dynamically_aligned = field.is_unsized()
+                && match field.ty.kind() {
+                    TyKind::Slice(..) | TyKind::Str | TyKind::Foreign(..) => false,
+                    TyKind::Adt(adt_def, _) => !adt_def.repr().packed(),
+                    _ => true,
+                };
+            if is_dynamically_aligned {
+                let (_, align) = self.size_and_align_of(field, base.llextra);
+                lloffset = self.round_up_to_alignment(lloffset, align);
+            }
+            let mut indices = [lloffset];
+            ptr = LLVMBuildBitCast(self.llbx, ptr, i8p_type(self.llcx), name.as_ptr());
+            ptr = LLVMBuildInBoundsGEP2(
+                self.llbx,
+                LLVMInt8TypeInContext(self.llcx),
+                ptr,
+                indices.as_mut_ptr(),
+                indices.len() as core::ffi::c_uint,
+                name.as_ptr(),
+            );
+        }
+        let field_llty = ty_to_llvm_type(self.llcx, self.tcx, field.ty);
+        let ptr = LLVMBuildBitCast(
+            self.llbx,
+            ptr,
+            LLVMPointerType(field_llty, 0),
+            name.as_ptr(),
+        );
+
+        TPlace {
+            llextra: if field.is_sized() { None } else { base.llextra },
+            // Fields of packed structs may be less aligned than their type.
+            align: base.align.restrict_for_offset(offset),
+            ..TPlace::new_ref(field, ptr)
+        }
+    }
+
+    /// Returns a pointer of type `ptr_ty` to `place`. Zero-sized places have no storage, so they
+    /// get a dangling but well-aligned pointer instead.
+    unsafe fn place_to_pointer(&mut self, place: &TPlace<'tcx>, ptr_ty: Ty<'tcx>) -> LLVMValueRef {
+        let name = c_string!("");
+        let ptr_llty = ty_to_llvm_type(self.llcx, self.tcx, ptr_ty);
+        let data_llty = match place.llextra {
+            Some(_) => LLVMStructGetTypeAtIndex(ptr_llty, 0),
+            None => ptr_llty,
+        };
+        let data = match place.llval.get() {
+            Some(llval) => LLVMBuildBitCast(self.llbx, *llval, data_llty, name.as_ptr()),
+            None => LLVMConstIntToPtr(
+                LLVMConstInt(
+                    isize_type(self.llcx, self.tcx),
+                    place.ty_and_layout.align.abi.bytes(),
+                    0,
+                ),
+                data_llty,
+            ),
+        };
+
+        match place.llextra {
+            Some(meta) => self.build_pair(ptr_llty, data, meta),
+            None => data,
+        }
+    }
+
+    unsafe fn build_pair(
+        &mut self,
+        pair_llty: LLVMTypeRef,
+        a: LLVMValueRef,
+        b: LLVMValueRef,
+    ) -> LLVMValueRef {
+        let name = c_string!("");
+        let pair = LLVMGetUndef(pair_llty);
+        let pair = LLVMBuildInsertValue(self.llbx, pair, a, 0, name.as_ptr());
+        LLVMBuildInsertValue(self.llbx, pair, b, 1, name.as_ptr())
+    }
+
+    /// Turns a thin pointer into a fat one, e.g. `&T` into `&dyn Trait`.
+    unsafe fn codegen_unsize(&mut self, operand: TPlace<'tcx>, cast_ty: Ty<'tcx>) -> LLVMValueRef {
+        let name = c_string!("");
+        let source = operand.ty().builtin_deref(true).unwrap().ty;
+        let target = cast_ty.builtin_deref(true).unwrap().ty;
+        let (source_tail, target_tail) = self.tcx.struct_lockstep_tails_erasing_lifetimes(
+            source,
+            target,
+            ParamEnv::reveal_all(),
+        );
+
+        let cast_llty = ty_to_llvm_type(self.llcx, self.tcx, cast_ty);
+        let (data, meta) = match (source_tail.kind(), target_tail.kind()) {
+            (TyKind::Dynamic(..), TyKind::Dynamic(..)) => (
+                LLVMBuildExtractValue(self.llbx, *operand.llval(), 0, name.as_ptr()),
+                LLVMBuildExtractValue(self.llbx, *operand.llval(), 1, name.as_ptr()),
+            ),
+            (TyKind::Array(_, len), TyKind::Slice(_)) => (
+                *operand.llval(),
+                LLVMConstInt(
+                    isize_type(self.llcx, self.tcx),
+                    len.eval_usize(self.tcx, ParamEnv::reveal_all()),
+                    0,
+                ),
+            ),
+            (_, TyKind::Dynamic(data, ..)) => (
+                *operand.llval(),
+                get_vtable(
+                    self.llcx,
+                    self.llmod,
+                    self.tcx,
+                    source_tail,
+                    data.principal(),
+                ),
+            ),
+            _ => todo!(),
+        };
+        let data = LLVMBuildBitCast(
+            self.llbx,
+            data,
+            LLVMStructGetTypeAtIndex(cast_llty, 0),
+            name.as_ptr(),
+        );
+
+        self.build_pair(cast_llty, data, meta)
+    }
+
+    /// Returns `instance` as a value of the function pointer type `cast_ty`.
+    unsafe fn codegen_fn_pointer(
+        &mut self,
+        instance: Instance<'tcx>,
+        cast_ty: Ty<'tcx>,
+    ) -> LLVMValueRef {
+        let llfn = get_fn(self.llcx, self.llmod, self.tcx, instance);
+        LLVMConstBitCast(llfn, ty_to_llvm_type(self.llcx, self.tcx, cast_ty))
+    }
+
+    /// Converts between integers, including `bool` and `char`, and floats. Like `as`, casts from
+    /// floats to integers saturate, and map NaN to 0.
+    unsafe fn codegen_numeric_cast(
+        &mut self,
+        operand: TPlace<'tcx>,
+        cast_ty: Ty<'tcx>,
+    ) -> LLVMValueRef {
+        let name = c_string!("");
+        let llval = *operand.llval();
+        let cast_llty = ty_to_llvm_type(self.llcx, self.tcx, cast_ty);
+        let from_signed = operand.ty().is_signed();
+
+        match (
+            operand.ty().is_floating_point(),
+            cast_ty.is_floating_point(),
+        ) {
+            (false, false) => {
+                LLVMBuildIntCast2(self.llbx, llval, cast_llty, from_signed as _, name.as_ptr())
+            }
+            (false, true) if from_signed => {
+                LLVMBuildSIToFP(self.llbx, llval, cast_llty, name.as_ptr())
+            }
+            (false, true) => LLVMBuildUIToFP(self.llbx, llval, cast_llty, name.as_ptr()),
+            (true, false) => {
+                let sign = if cast_ty.is_signed() { "si" } else { "ui" };
+                self.call_intrinsic(
+                    &format!("llvm.fpto{}.sat", sign),
+                    &[cast_llty, LLVMTypeOf(llval)],
+                    &[llval],
+                )
+            }
+            (true, true) => LLVMBuildFPCast(self.llbx, llval, cast_llty, name.as_ptr()),
+        }
+    }
+
+    unsafe fn codegen_ptr_to_ptr(
+        &mut self,
+        operand: TPlace<'tcx>,
+        cast_ty: Ty<'tcx>,
+    ) -> LLVMValueRef {
+        let name = c_string!("");
+        let cast_llty = ty_to_llvm_type(self.llcx, self.tcx, cast_ty);
+        let llval = *operand.llval();
+        let is_fat = |llty| matches!(LLVMGetTypeKind(llty), LLVMTypeKind::LLVMStructTypeKind);
+
+        match (is_fat(LLVMTypeOf(llval)), is_fat(cast_llty)) {
+            (false, false) => LLVMBuildBitCast(self.llbx, llval, cast_llty, name.as_ptr()),
+            // Casting to a thin pointer drops the metadata.
+            (true, false) => {
+                let data = LLVMBuildExtractValue(self.llbx, llval, 0, name.as_ptr());
+                LLVMBuildBitCast(self.llbx, data, cast_llty, name.as_ptr())
+            }
+            (true, true) => {
+                let data = LLVMBuildExtractValue(self.llbx, llval, 0, name.as_ptr());
+                let data = LLVMBuildBitCast(
+                    self.llbx,
+                    data,
+                    LLVMStructGetTypeAtIndex(cast_llty, 0),
+                    name.as_ptr(),
+                );
+                let meta = LLVMBuildExtractValue(self.llbx, llval, 1, name.as_ptr());
+                self.build_pair(cast_llty, data, meta)
+            }
+            (false, true) => unreachable!(),
+        }
+    }
+
+    pub(crate) unsafe fn codegen_operand(&mut self, operand: &Operand<'tcx>) -> TPlace<'tcx> {
+        match operand {
+            mir::Operand::Copy(place) | mir::Operand::Move(place) => self.codegen_place(place),
+            mir::Operand::Constant(constant) => {
+                let layout = self.layout_of(self.monomorphize(constant.ty()));
+                let literal = self
+                    .monomorphize(constant.literal)
+                    .eval(self.tcx, ParamEnv::reveal_all());
+                match literal {
+                    mir::ConstantKind::Val(val, _) => self.codegen_const_value(layout, val),
+                    _ => todo!(),
                 }
             }
         }
     }
 
+    pub(crate) unsafe fn codegen_const_value(
+        &mut self,
+        layout: TyAndLayout<'tcx>,
+        val: ConstValue<'tcx>,
+    ) -> TPlace<'tcx> {
+        if layout.is_zst() {
+            return TPlace::new(layout);
+        }
+
+        match val {
+            ConstValue::Scalar(scalar) => self.codegen_scalar(layout, scalar),
+            ConstValue::ByRef { alloc, offset } => {
+                let global = add_const_alloc(self.llcx, self.llmod, self.tcx, "", alloc);
+                let ptr = LLVMConstBitCast(
+                    const_gep(self.llcx, global, offset),
+                    LLVMPointerType(ty_to_llvm_type(self.llcx, self.tcx, layout.ty), 0),
+                );
+                TPlace::new_ref(layout, ptr)
+            }
+            // String literals and byte strings become a pointer to their data paired with their
+            // length.
+            ConstValue::Slice { data, start, end } => {
+                let llty = ty_to_llvm_type(self.llcx, self.tcx, layout.ty);
+                let ptr = const_slice_data(self.llcx, self.llmod, self.tcx, data, start, end);
+                let elem = self.layout_of(layout.ty.builtin_deref(true).unwrap().ty);
+                let elem = elem.field(&layout_cx(self.tcx), 0);
+                let len = (end - start) as u64 / elem.size.bytes();
+
+                let mut fields = [
+                    LLVMConstBitCast(ptr, LLVMStructGetTypeAtIndex(llty, 0)),
+                    LLVMConstInt(isize_type(self.llcx, self.tcx), len, 0),
+                ];
+                let llval = LLVMConstStructInContext(
+                    self.llcx,
+                    fields.as_mut_ptr(),
+                    fields.len() as core::ffi::c_uint,
+                    0,
+                );
+                TPlace::new_immediate(layout, llval)
+            }
+            _ => todo!(),
+        }
+    }
+
+    /// Returns the `&'static str` constant of `string`.
+    unsafe fn codegen_str(&mut self, string: &str) -> LLVMValueRef {
+        let alloc = Allocation::from_bytes_byte_aligned_immutable(string.as_bytes());
+        let value = ConstValue::Slice {
+            data: self.tcx.intern_const_alloc(alloc),
+            start: 0,
+            end: string.len(),
+        };
+        let layout = self.layout_of(self.tcx.mk_static_str());
+        *self
+            .codegen_const_value(layout, value)
+            .load_scalar(self.llbx)
+            .llval()
+    }
+
+    unsafe fn codegen_scalar(&mut self, layout: TyAndLayout<'tcx>, scalar: Scalar) -> TPlace<'tcx> {
+        let llty = ty_to_llvm_type(self.llcx, self.tcx, layout.ty);
+        let llval = match scalar {
+            Scalar::Int(int) => {
+                let bits = int.to_bits(int.size()).unwrap();
+                let words = [bits as u64, (bits >> 64) as u64];
+                let int_llty = LLVMIntTypeInContext(self.llcx, int.size().bits() as _);
+                let llint = LLVMConstIntOfArbitraryPrecision(int_llty, 2, words.as_ptr());
+                match LLVMGetTypeKind(llty) {
+                    LLVMTypeKind::LLVMIntegerTypeKind => {
+                        LLVMConstIntOfArbitraryPrecision(llty, 2, words.as_ptr())
+                    }
+                    LLVMTypeKind::LLVMPointerTypeKind => LLVMConstIntToPtr(llint, llty),
+                    LLVMTypeKind::LLVMFloatTypeKind | LLVMTypeKind::LLVMDoubleTypeKind => {
+                        LLVMConstBitCast(llint, llty)
+                    }
+                    // Scalars such as `Option<&T>` are lowered to aggregates, which cannot be
+                    // built from an integer directly, so they are read back from memory instead.
+                    _ => {
+                        let global = LLVMAddGlobal(self.llmod, int_llty, c_string!("").as_ptr());
+                        LLVMSetInitializer(global, llint);
+                        LLVMSetGlobalConstant(global, 1);
+                        LLVMSetLinkage(global, llvm_sys::LLVMLinkage::LLVMPrivateLinkage);
+                        let ptr = LLVMConstBitCast(global, LLVMPointerType(llty, 0));
+                        return TPlace::new_ref(layout, ptr);
+                    }
+                }
+            }
+            Scalar::Ptr(ptr, _) => {
+                let (alloc_id, offset) = ptr.into_parts();
+                let llval = const_ptr_to_alloc(self.llcx, self.llmod, self.tcx, alloc_id, offset);
+                LLVMConstBitCast(llval, llty)
+            }
+        };
+
+        TPlace::new_immediate(layout, llval)
+    }
+
     unsafe fn codegen_terminator(&mut self, term: &Terminator<'tcx>) {
         debug!(?term.kind);
         match &term.kind {
             TerminatorKind::Goto { target } => {
                 LLVMBuildBr(self.llbx, self.basic_blocks[*target]);
             }
+            // Edges only borrowck follows, which the MIR cleanup passes normally remove already.
+            TerminatorKind::FalseEdge { real_target, .. }
+            | TerminatorKind::FalseUnwind { real_target, .. } => {
+                LLVMBuildBr(self.llbx, self.basic_blocks[*real_target]);
+            }
             TerminatorKind::SwitchInt { discr, targets, .. } => {
                 let operand = self.codegen_operand(discr).load_scalar(self.llbx);
                 let switch = LLVMBuildSwitch(
@@ -322,16 +1536,39 @@ impl<'tcx> FunctionCx<'tcx> {
                         self.basic_blocks[target],
                     );
                 }
+
+                // A value branched on with `likely` or `unlikely` only makes its expected target
+                // hot. The weight of the default destination comes first.
+                let expected = discr
+                    .place()
+                    .and_then(|place| place.as_local())
+                    .and_then(|local| self.expected_values[local])
+                    .map(|expected| expected as u128);
+                let is_expected = |value: Option<u128>| match (expected, value) {
+                    (None, _) => true,
+                    (Some(expected), Some(value)) => value == expected,
+                    (Some(expected), None) => targets.iter().all(|(value, _)| value != expected),
+                };
+                let weights = std::iter::once((None, targets.otherwise()))
+                    .chain(targets.iter().map(|(value, target)| (Some(value), target)))
+                    .map(|(value, target)| {
+                        if is_expected(value) {
+                            self.branch_weight(target)
+                        } else {
+                            COLD_WEIGHT
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                self.set_branch_weights(switch, &weights);
             }
             TerminatorKind::Return => {
                 let ret_data = &self.locals[mir::RETURN_PLACE];
-                match ret_data.ty().kind() {
-                    TyKind::Tuple(tuple) if tuple.len() == 0 => LLVMBuildRetVoid(self.llbx),
-                    _ => {
-                        let ret = *ret_data.clone().load_scalar(self.llbx).llval();
-                        LLVMBuildRet(self.llbx, ret)
-                    }
-                };
+                if ret_data.ty_and_layout.is_zst() {
+                    LLVMBuildRetVoid(self.llbx);
+                } else {
+                    let ret = *ret_data.clone().load_scalar(self.llbx).llval();
+                    LLVMBuildRet(self.llbx, ret);
+                }
             }
             TerminatorKind::Call {
                 func,
@@ -340,84 +1577,453 @@ impl<'tcx> FunctionCx<'tcx> {
                 target,
                 ..
             } => {
-                let (func_id, _) = func.const_fn_def().unwrap();
-                let fn_name = self.tcx.def_path_str(func_id);
-
-                let func = LLVMGetNamedFunction(self.llmod, c_string!(fn_name).as_ptr());
-                let func = if func.is_null() {
-                    codegen_fn(self.llcx, self.llmod, self.tcx, func_id)
-                } else {
-                    func
+                let func_ty = self.monomorphize(func.ty(self.mir, self.tcx));
+                // Function pointers have no instance, and are called with the signature of their
+                // type.
+                let instance = match func_ty.kind() {
+                    TyKind::FnDef(func_id, substs) => Some(
+                        Instance::resolve(self.tcx, ParamEnv::reveal_all(), *func_id, substs)
+                            .unwrap()
+                            .unwrap(),
+                    ),
+                    _ => None,
                 };
+                if let Some(
+                    instance @ Instance {
+                        def: InstanceDef::Intrinsic(_),
+                        ..
+                    },
+                ) = instance
+                {
+                    let dest = self.codegen_place(destination);
+                    self.codegen_intrinsic_call(instance, args, dest, term.source_info.span);
+                    match target {
+                        Some(target) => LLVMBuildBr(self.llbx, self.basic_blocks[*target]),
+                        None => LLVMBuildUnreachable(self.llbx),
+                    };
+                    return;
+                }
 
-                let func_type = LLVMTypeOf(func);
-                let ret_type = LLVMGetReturnType(func_type);
                 let mut args = args
                     .iter()
-                    .map(|arg| *self.codegen_operand(arg).load_scalar(self.llbx).llval())
+                    .map(|arg| self.codegen_operand(arg))
                     .collect::<Vec<_>>();
+                // The tupled arguments of "rust-call" functions are passed field by field.
+                if func_ty.fn_sig(self.tcx).abi() == FnAbi::RustCall {
+                    let tupled = args.pop().unwrap();
+                    for idx in 0..tupled.ty_and_layout.fields.count() {
+                        args.push(self.project_field(tupled.clone(), idx));
+                    }
+                }
+                let mut args = args
+                    .into_iter()
+                    .filter(|arg| !arg.ty_and_layout.is_zst())
+                    .map(|arg| *arg.load_scalar(self.llbx).llval())
+                    .collect::<Vec<_>>();
+                if instance.map_or(false, |instance| {
+                    instance.def.requires_caller_location(self.tcx)
+                }) {
+                    args.push(self.get_caller_location(term.source_info.span));
+                }
+                let (func_type, func) = match instance {
+                    // The receiver is a trait object, whose method is looked up in its vtable
+                    // and called with the data pointer alone.
+                    Some(
+                        instance @ Instance {
+                            def: InstanceDef::Virtual(_, idx),
+                            ..
+                        },
+                    ) => {
+                        let name = c_string!("");
+                        let receiver = args[0];
+                        args[0] = LLVMBuildExtractValue(self.llbx, receiver, 0, name.as_ptr());
+                        args[0] = LLVMBuildBitCast(
+                            self.llbx,
+                            args[0],
+                            i8p_type(self.llcx),
+                            name.as_ptr(),
+                        );
+                        let vtable = LLVMBuildExtractValue(self.llbx, receiver, 1, name.as_ptr());
+
+                        let fn_sig = instance_fn_sig(self.tcx, instance);
+                        let func_type = virtual_fn_type(self.llcx, self.tcx, fn_sig);
+                        let func = load_virtual_fn(self.llcx, self.llbx, vtable, idx, func_type);
+                        (func_type, func)
+                    }
+                    Some(instance) => {
+                        let func = get_fn(self.llcx, self.llmod, self.tcx, instance);
+                        (LLVMGetElementType(LLVMTypeOf(func)), func)
+                    }
+                    None => {
+                        let func = *self.codegen_operand(func).load_scalar(self.llbx).llval();
+                        (LLVMGetElementType(LLVMTypeOf(func)), func)
+                    }
+                };
+
                 let ret = LLVMBuildCall2(
                     self.llbx,
-                    ret_type,
+                    func_type,
                     func,
                     args.as_mut_ptr(),
                     args.len() as u32,
                     c_string!("").as_ptr(),
                 );
 
-                let ptr = self.locals[destination.local].llval();
-                LLVMBuildStore(self.llbx, ret, *ptr);
-                if let Some(target) = target {
+                let dest = self.codegen_place(destination);
+                dest.store_llval(self.llbx, ret);
+                // Calls without a target diverge.
+                match target {
+                    Some(target) => LLVMBuildBr(self.llbx, self.basic_blocks[*target]),
+                    None => LLVMBuildUnreachable(self.llbx),
+                };
+            }
+            TerminatorKind::Drop { place, target, .. } => {
+                let place = self.codegen_place(place);
+                self.codegen_drop(place);
+                LLVMBuildBr(self.llbx, self.basic_blocks[*target]);
+            }
+            TerminatorKind::DropAndReplace {
+                place,
+                value,
+                target,
+                ..
+            } => {
+                let place = self.codegen_place(place);
+                self.codegen_drop(place.clone());
+                let value = self.codegen_operand(value);
+                value.load_scalar(self.llbx).store(self.llbx, &place);
+                LLVMBuildBr(self.llbx, self.basic_blocks[*target]);
+            }
+            TerminatorKind::Assert {
+                cond,
+                expected,
+                msg,
+                target,
+                ..
+            } => {
+                let cond = *self.codegen_operand(cond).load_scalar(self.llbx).llval();
+                // Like `-self`, which has no checked operation of its own, the negation check
+                // only fails with overflow checks on. Constant conditions may never fail either.
+                let never_fails = (!self.tcx.sess.overflow_checks()
+                    && matches!(msg, AssertKind::OverflowNeg(_)))
+                    || (!LLVMIsAConstantInt(cond).is_null()
+                        && (LLVMConstIntGetZExtValue(cond) != 0) == *expected);
+                if never_fails {
                     LLVMBuildBr(self.llbx, self.basic_blocks[*target]);
+                    return;
                 }
+
+                let failure = LLVMAppendBasicBlockInContext(
+                    self.llcx,
+                    self.llfn,
+                    c_string!("panic").as_ptr(),
+                );
+                let (then_block, else_block) = if *expected {
+                    (self.basic_blocks[*target], failure)
+                } else {
+                    (failure, self.basic_blocks[*target])
+                };
+                let br = LLVMBuildCondBr(self.llbx, cond, then_block, else_block);
+                let weights = if *expected {
+                    [self.branch_weight(*target), COLD_WEIGHT]
+                } else {
+                    [COLD_WEIGHT, self.branch_weight(*target)]
+                };
+                self.set_branch_weights(br, &weights);
+
+                LLVMPositionBuilderAtEnd(self.llbx, failure);
+                let (lang_item, mut args) = match msg {
+                    AssertKind::BoundsCheck { len, index } => {
+                        let index = *self.codegen_operand(index).load_scalar(self.llbx).llval();
+                        let len = *self.codegen_operand(len).load_scalar(self.llbx).llval();
+                        (LangItem::PanicBoundsCheck, vec![index, len])
+                    }
+                    msg => (LangItem::Panic, vec![self.codegen_str(msg.description())]),
+                };
+                args.push(self.get_caller_location(term.source_info.span));
+                let def_id = self
+                    .tcx
+                    .require_lang_item(lang_item, Some(term.source_info.span));
+                let instance = Instance::mono(self.tcx, def_id);
+                let func = get_fn(self.llcx, self.llmod, self.tcx, instance);
+                LLVMBuildCall2(
+                    self.llbx,
+                    LLVMGetElementType(LLVMTypeOf(func)),
+                    func,
+                    args.as_mut_ptr(),
+                    args.len() as u32,
+                    c_string!("").as_ptr(),
+                );
+                LLVMBuildUnreachable(self.llbx);
             }
-            TerminatorKind::Assert { target, .. } => {
-                warn!("codegen does not fully support `{:?}`", term.kind);
-                LLVMBuildBr(self.llbx, self.basic_blocks[*target]);
+            // Calls never unwind, so cleanup blocks are never entered.
+            TerminatorKind::Resume => {
+                LLVMBuildUnreachable(self.llbx);
             }
-            _ => {
-                todo!()
+            TerminatorKind::Unreachable => {
+                LLVMBuildUnreachable(self.llbx);
+            }
+            TerminatorKind::Abort => {
+                self.call_intrinsic("llvm.trap", &[], &[]);
+                LLVMBuildUnreachable(self.llbx);
             }
+            // The generator transform turns these into returns from the resume function and from
+            // the drop shim of the generator.
+            TerminatorKind::Yield { .. } | TerminatorKind::GeneratorDrop => {
+                unreachable!("`{:?}` after the generator transform", term.kind)
+            }
+            TerminatorKind::InlineAsm {
+                template,
+                operands,
+                options,
+                destination,
+                ..
+            } => {
+                self.codegen_inline_asm(template, operands, *options, *destination);
+            }
+        }
+    }
+
+    /// Returns the weight of the branch to `target`, following the weights LLVM gives `likely` and
+    /// `unlikely` branches.
+    fn branch_weight(&self, target: mir::BasicBlock) -> u32 {
+        if self.cold_blocks.contains(target) {
+            COLD_WEIGHT
+        } else {
+            HOT_WEIGHT
         }
     }
+
+    /// Attaches `!prof` branch weights to `branch`, which LLVM uses to lay out cold blocks out of
+    /// the way. Branches whose destinations are all alike are left without them.
+    unsafe fn set_branch_weights(&self, branch: LLVMValueRef, weights: &[u32]) {
+        if weights.iter().all(|weight| *weight == weights[0]) {
+            return;
+        }
+
+        let name = "branch_weights";
+        let name = LLVMMDStringInContext(self.llcx, name.as_ptr() as *const _, name.len() as _);
+        let i32 = LLVMInt32TypeInContext(self.llcx);
+        let weights = weights
+            .iter()
+            .map(|weight| LLVMConstInt(i32, *weight as u64, 0));
+        let mut operands = std::iter::once(name).chain(weights).collect::<Vec<_>>();
+        let node = LLVMMDNodeInContext(self.llcx, operands.as_mut_ptr(), operands.len() as _);
+        let kind =
+            LLVMGetMDKindIDInContext(self.llcx, "prof".as_ptr() as *const _, "prof".len() as _);
+        LLVMSetMetadata(branch, kind, node);
+    }
+
+    /// Returns the `&'static Location` of `span`. `#[track_caller]` functions pass on the location
+    /// of their own caller instead.
+    pub(crate) unsafe fn get_caller_location(&mut self, span: Span) -> LLVMValueRef {
+        if let Some(caller_location) = self.caller_location {
+            return caller_location;
+        }
+
+        // Locations inside macros point at the outermost macro invocation.
+        let topmost = span.ctxt().outer_expn().expansion_cause().unwrap_or(span);
+        let caller = self.tcx.sess.source_map().lookup_char_pos(topmost.lo());
+        let location = self.tcx.const_caller_location((
+            Symbol::intern(&caller.file.name.prefer_remapped().to_string_lossy()),
+            caller.line as u32,
+            caller.col_display as u32 + 1,
+        ));
+        let layout = self.layout_of(self.tcx.caller_location_ty());
+        *self
+            .codegen_const_value(layout, location)
+            .load_scalar(self.llbx)
+            .llval()
+    }
+
+    /// Declares the LLVM intrinsic `name` overloaded on `types` and returns it with its type.
+    pub(crate) unsafe fn get_intrinsic(
+        &mut self,
+        name: &str,
+        types: &[LLVMTypeRef],
+    ) -> (LLVMTypeRef, LLVMValueRef) {
+        let id = LLVMLookupIntrinsicID(name.as_ptr() as *const _, name.len());
+        let mut types = types.to_vec();
+        let llfn = LLVMGetIntrinsicDeclaration(self.llmod, id, types.as_mut_ptr(), types.len());
+        let llfn_ty = LLVMIntrinsicGetType(self.llcx, id, types.as_mut_ptr(), types.len());
+
+        (llfn_ty, llfn)
+    }
+
+    pub(crate) unsafe fn call_intrinsic(
+        &mut self,
+        name: &str,
+        types: &[LLVMTypeRef],
+        args: &[LLVMValueRef],
+    ) -> LLVMValueRef {
+        let (llfn_ty, llfn) = self.get_intrinsic(name, types);
+        let mut args = args.to_vec();
+        LLVMBuildCall2(
+            self.llbx,
+            llfn_ty,
+            llfn,
+            args.as_mut_ptr(),
+            args.len() as u32,
+            c_string!("").as_ptr(),
+        )
+    }
+
+    unsafe fn codegen_drop(&mut self, place: TPlace<'tcx>) {
+        let name = c_string!("");
+        let drop_in_place = Instance::resolve_drop_in_place(self.tcx, place.ty());
+        if let InstanceDef::DropGlue(_, None) = drop_in_place.def {
+            return;
+        }
+
+        let (func_type, func, arg) = match place.ty().kind() {
+            // Trait objects are dropped through the `drop_in_place` of their vtable.
+            TyKind::Dynamic(..) => {
+                let i8p = i8p_type(self.llcx);
+                let mut params = [i8p];
+                let func_type = LLVMFunctionType(
+                    LLVMVoidTypeInContext(self.llcx),
+                    params.as_mut_ptr(),
+                    params.len() as core::ffi::c_uint,
+                    0,
+                );
+                let func = load_virtual_fn(
+                    self.llcx,
+                    self.llbx,
+                    place.llextra.unwrap(),
+                    DROP_IN_PLACE,
+                    func_type,
+                );
+                let arg = LLVMBuildBitCast(self.llbx, *place.llval(), i8p, name.as_ptr());
+                (func_type, func, arg)
+            }
+            _ => {
+                let func = get_fn(self.llcx, self.llmod, self.tcx, drop_in_place);
+                let func_type = LLVMGetElementType(LLVMTypeOf(func));
+                let ptr_ty = self.tcx.mk_mut_ptr(place.ty());
+                (func_type, func, self.place_to_pointer(&place, ptr_ty))
+            }
+        };
+
+        let mut args = [arg];
+        LLVMBuildCall2(
+            self.llbx,
+            func_type,
+            func,
+            args.as_mut_ptr(),
+            args.len() as u32,
+            name.as_ptr(),
+        );
+    }
+}
+
+/// Returns the byte that `llval` consists of if it is an integer constant of `size` bytes which
+/// are all the same.
+unsafe fn splat_byte(llval: LLVMValueRef, size: u64) -> Option<u8> {
+    if LLVMIsAConstantInt(llval).is_null() || size > 8 {
+        return None;
+    }
+
+    let val = LLVMConstIntGetZExtValue(llval);
+    let byte = val as u8;
+    (0..size)
+        .all(|idx| (val >> (idx * 8)) as u8 == byte)
+        .then_some(byte)
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct TPlace<'tcx> {
     ty_and_layout: TyAndLayout<'tcx>,
-    llval: OnceCell<LLVMValueRef>,
+    /// Shared between clones, so that assigning an SSA local through any of them defines it.
+    llval: Rc<OnceCell<LLVMValueRef>>,
+    /// Alignment of the memory `llval` points to, which is lower than that of the type for
+    /// fields of packed structs.
+    align: Align,
+    /// Metadata of an unsized place, e.g. the vtable of a trait object.
+    llextra: Option<LLVMValueRef>,
+    /// Whether `llval` is the value itself rather than a pointer to it.
+    immediate: bool,
 }
 
 impl<'tcx> TPlace<'tcx> {
+    pub(crate) fn new(ty_and_layout: TyAndLayout<'tcx>) -> Self {
+        TPlace {
+            ty_and_layout,
+            llval: Rc::new(OnceCell::new()),
+            align: ty_and_layout.align.abi,
+            llextra: None,
+            immediate: false,
+        }
+    }
+
+    pub(crate) fn new_ref(ty_and_layout: TyAndLayout<'tcx>, llval: LLVMValueRef) -> Self {
+        TPlace {
+            llval: Rc::new(OnceCell::from(llval)),
+            ..TPlace::new(ty_and_layout)
+        }
+    }
+
+    pub(crate) fn new_immediate(ty_and_layout: TyAndLayout<'tcx>, llval: LLVMValueRef) -> Self {
+        TPlace {
+            immediate: true,
+            ..TPlace::new_ref(ty_and_layout, llval)
+        }
+    }
+
     pub(crate) fn ty(&self) -> Ty<'tcx> {
         self.ty_and_layout.ty
     }
 
+    pub(crate) fn layout(&self) -> TyAndLayout<'tcx> {
+        self.ty_and_layout
+    }
+
+    pub(crate) fn align(&self) -> Align {
+        self.align
+    }
+
     pub(crate) fn llval(&self) -> &LLVMValueRef {
         self.llval.get().unwrap()
     }
 
     pub(crate) unsafe fn load_scalar(self, llbx: LLVMBuilderRef) -> Self {
-        let llval = self.llval();
-        let llval_type = LLVMTypeOf(*llval);
-        if matches!(
-            LLVMGetTypeKind(llval_type),
-            LLVMTypeKind::LLVMPointerTypeKind
-        ) {
-            let llval_element_type = LLVMGetElementType(llval_type);
-            let llval = LLVMBuildLoad2(llbx, llval_element_type, *llval, c_string!("").as_ptr());
+        if self.immediate {
+            return self;
+        }
+        // Zero-sized values carry no data, so an undefined empty struct stands in for them.
+        if self.ty_and_layout.is_zst() {
+            let llfn = LLVMGetBasicBlockParent(LLVMGetInsertBlock(llbx));
+            let llcx = LLVMGetTypeContext(LLVMTypeOf(llfn));
+            let llty = LLVMStructTypeInContext(llcx, std::ptr::null_mut(), 0, 0);
+            return TPlace::new_immediate(self.ty_and_layout, LLVMGetUndef(llty));
+        }
 
-            return TPlace {
-                ty_and_layout: self.ty_and_layout,
-                llval: OnceCell::from(llval),
-            };
+        let llval = self.llval();
+        let llval_element_type = LLVMGetElementType(LLVMTypeOf(*llval));
+        let llval = LLVMBuildLoad2(llbx, llval_element_type, *llval, c_string!("").as_ptr());
+        LLVMSetAlignment(llval, self.align.bytes() as core::ffi::c_uint);
+        if let Abi::Scalar(scalar) = self.ty_and_layout.abi {
+            apply_range_metadata(llval, scalar);
         }
 
-        self
+        TPlace::new_immediate(self.ty_and_layout, llval)
     }
 
-    pub(crate) unsafe fn store(self, llbx: LLVMBuilderRef, ptr: LLVMValueRef) {
-        let llval = self.llval();
-        LLVMBuildStore(llbx, *llval, ptr);
+    /// Stores this immediate into `dest`.
+    pub(crate) unsafe fn store(self, llbx: LLVMBuilderRef, dest: &TPlace<'tcx>) {
+        dest.store_llval(llbx, *self.llval());
+    }
+
+    /// Stores `llval` into the memory this place points to. Zero-sized places have no memory, and
+    /// SSA locals take `llval` as their value.
+    pub(crate) unsafe fn store_llval(&self, llbx: LLVMBuilderRef, llval: LLVMValueRef) {
+        if self.ty_and_layout.is_zst() {
+            return;
+        }
+        if self.immediate {
+            self.llval.set(llval).unwrap();
+            return;
+        }
+        let store = LLVMBuildStore(llbx, llval, *self.llval());
+        LLVMSetAlignment(store, self.align.bytes() as core::ffi::c_uint);
     }
 }
@@ -0,0 +1,285 @@
+use rustc_const_eval::interpret::{read_target_uint, AllocId, ConstAllocation, GlobalAlloc};
+use rustc_hir::{def_id::DefId, Mutability};
+use rustc_middle::{
+    mir::mono::Linkage,
+    ty::{self, Instance, TyCtxt},
+};
+use rustc_target::{abi::Size, spec::TlsModel};
+
+use llvm_sys::{core::*, prelude::*, LLVMLinkage, LLVMThreadLocalMode, LLVMUnnamedAddr};
+
+use std::{cell::RefCell, collections::HashMap, ffi::CString};
+
+use crate::{
+    c_string,
+    codegen::get_fn,
+    ty::{i8p_type, ty_to_llvm_type},
+    vtable::get_vtable,
+};
+
+/// Lowers the bytes of `alloc` to a packed LLVM struct, with pointers in place of its relocations.
+pub(crate) unsafe fn const_alloc_to_llvm<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    alloc: ConstAllocation<'tcx>,
+) -> LLVMValueRef {
+    let alloc = alloc.inner();
+    let bytes = alloc.inspect_with_uninit_and_ptr_outside_interpreter(0..alloc.len());
+    let pointer_size = tcx.data_layout.pointer_size.bytes() as usize;
+
+    let mut llvals = Vec::new();
+    let mut next_offset = 0;
+    for &(offset, alloc_id) in alloc.relocations().iter() {
+        let offset = offset.bytes() as usize;
+        if offset > next_offset {
+            llvals.push(const_bytes(llcx, &bytes[next_offset..offset]));
+        }
+
+        let ptr_offset = read_target_uint(
+            tcx.data_layout.endian,
+            &bytes[offset..offset + pointer_size],
+        )
+        .unwrap();
+        llvals.push(const_ptr_to_alloc(
+            llcx,
+            llmod,
+            tcx,
+            alloc_id,
+            Size::from_bytes(ptr_offset),
+        ));
+        next_offset = offset + pointer_size;
+    }
+    if bytes.len() > next_offset {
+        llvals.push(const_bytes(llcx, &bytes[next_offset..]));
+    }
+
+    LLVMConstStructInContext(
+        llcx,
+        llvals.as_mut_ptr(),
+        llvals.len() as core::ffi::c_uint,
+        1,
+    )
+}
+
+unsafe fn const_bytes(llcx: LLVMContextRef, bytes: &[u8]) -> LLVMValueRef {
+    LLVMConstStringInContext(
+        llcx,
+        bytes.as_ptr() as *const _,
+        bytes.len() as core::ffi::c_uint,
+        1,
+    )
+}
+
+/// Emits `alloc` as a private global named `name` and returns it as an `i8*`.
+pub(crate) unsafe fn add_const_alloc<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    name: &str,
+    alloc: ConstAllocation<'tcx>,
+) -> LLVMValueRef {
+    let init = const_alloc_to_llvm(llcx, llmod, tcx, alloc);
+    let global = LLVMAddGlobal(llmod, LLVMTypeOf(init), c_string!(name).as_ptr());
+    LLVMSetInitializer(global, init);
+    LLVMSetGlobalConstant(global, (alloc.inner().mutability == Mutability::Not) as _);
+    LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+    LLVMSetAlignment(global, alloc.inner().align.bytes() as core::ffi::c_uint);
+
+    LLVMConstBitCast(global, i8p_type(llcx))
+}
+
+/// Returns an `i8*` to the bytes `start..end` of `alloc`. Allocations without relocations, such as
+/// string literals, become `unnamed_addr` globals of just those bytes, shared by every literal of
+/// the module with the same contents.
+pub(crate) unsafe fn const_slice_data<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    alloc: ConstAllocation<'tcx>,
+    start: usize,
+    end: usize,
+) -> LLVMValueRef {
+    thread_local! {
+        static SLICES: RefCell<HashMap<LLVMModuleRef, HashMap<Vec<u8>, LLVMValueRef>>> =
+            RefCell::new(HashMap::new());
+    }
+
+    let inner = alloc.inner();
+    if !inner.relocations().is_empty() {
+        let global = add_const_alloc(llcx, llmod, tcx, "", alloc);
+        return const_gep(llcx, global, Size::from_bytes(start));
+    }
+
+    let bytes = inner.inspect_with_uninit_and_ptr_outside_interpreter(start..end);
+    let align = inner.align.bytes() as core::ffi::c_uint;
+    let global = SLICES.with(|slices| {
+        let mut slices = slices.borrow_mut();
+        let globals = slices.entry(llmod).or_default();
+        if let Some(&global) = globals.get(bytes) {
+            LLVMSetAlignment(global, LLVMGetAlignment(global).max(align));
+            return global;
+        }
+
+        let init = const_bytes(llcx, bytes);
+        let global = LLVMAddGlobal(llmod, LLVMTypeOf(init), c_string!("str").as_ptr());
+        LLVMSetInitializer(global, init);
+        LLVMSetGlobalConstant(global, 1);
+        LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+        LLVMSetUnnamedAddress(global, LLVMUnnamedAddr::LLVMGlobalUnnamedAddr);
+        LLVMSetAlignment(global, align);
+        globals.insert(bytes.to_vec(), global);
+        global
+    });
+
+    LLVMConstBitCast(global, i8p_type(llcx))
+}
+
+/// Returns an `i8*` pointing `offset` bytes into the global allocation `alloc_id`.
+pub(crate) unsafe fn const_ptr_to_alloc<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    alloc_id: AllocId,
+    offset: Size,
+) -> LLVMValueRef {
+    let base = match tcx.global_alloc(alloc_id) {
+        GlobalAlloc::Memory(alloc) => {
+            let name = format!("{:?}", alloc_id);
+            let global = LLVMGetNamedGlobal(llmod, c_string!(name.as_str()).as_ptr());
+            if global.is_null() {
+                add_const_alloc(llcx, llmod, tcx, &name, alloc)
+            } else {
+                global
+            }
+        }
+        GlobalAlloc::Function(instance) => get_fn(llcx, llmod, tcx, instance),
+        GlobalAlloc::VTable(ty, trait_ref) => get_vtable(llcx, llmod, tcx, ty, trait_ref),
+        GlobalAlloc::Static(def_id) => get_static(llcx, llmod, tcx, def_id),
+    };
+
+    const_gep(llcx, LLVMConstBitCast(base, i8p_type(llcx)), offset)
+}
+
+/// Returns the global of the static `def_id`, emitting it first if the module does not have it yet.
+/// `#[thread_local]` statics become `thread_local` globals with the TLS model of the target.
+pub(crate) unsafe fn get_static<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    def_id: DefId,
+) -> LLVMValueRef {
+    let symbol = tcx.symbol_name(Instance::mono(tcx, def_id)).name;
+    let linkage = tcx.codegen_fn_attrs(def_id).linkage;
+    if let Some(linkage) = linkage.filter(|_| tcx.is_foreign_item(def_id)) {
+        return get_extern_with_linkage(llcx, llmod, tcx, def_id, symbol, linkage);
+    }
+
+    let name = c_string!(symbol);
+    let global = LLVMGetNamedGlobal(llmod, name.as_ptr());
+    if !global.is_null() {
+        return global;
+    }
+
+    let global = if tcx.is_foreign_item(def_id) {
+        // Foreign statics are defined elsewhere and resolved by their symbol name.
+        let ty = tcx.type_of(def_id);
+        LLVMAddGlobal(llmod, ty_to_llvm_type(llcx, tcx, ty), name.as_ptr())
+    } else {
+        let alloc = tcx.eval_static_initializer(def_id).unwrap();
+        let init = const_alloc_to_llvm(llcx, llmod, tcx, alloc);
+        let global = LLVMAddGlobal(llmod, LLVMTypeOf(init), name.as_ptr());
+        LLVMSetInitializer(global, init);
+        // Statics with interior mutability are interned as mutable.
+        LLVMSetGlobalConstant(global, (alloc.inner().mutability == Mutability::Not) as _);
+        LLVMSetLinkage(global, LLVMLinkage::LLVMInternalLinkage);
+        LLVMSetAlignment(global, alloc.inner().align.bytes() as core::ffi::c_uint);
+        global
+    };
+    if tcx.is_thread_local_static(def_id) {
+        let mode = match tcx.sess.tls_model() {
+            TlsModel::GeneralDynamic => LLVMThreadLocalMode::LLVMGeneralDynamicTLSModel,
+            TlsModel::LocalDynamic => LLVMThreadLocalMode::LLVMLocalDynamicTLSModel,
+            TlsModel::InitialExec => LLVMThreadLocalMode::LLVMInitialExecTLSModel,
+            TlsModel::LocalExec => LLVMThreadLocalMode::LLVMLocalExecTLSModel,
+        };
+        LLVMSetThreadLocalMode(global, mode);
+    }
+
+    global
+}
+
+/// Returns an internal global holding the address of the foreign static `symbol`, declared with
+/// `linkage`. Weak symbols may be missing at run time, so only a nullable pointer can refer to
+/// them.
+unsafe fn get_extern_with_linkage<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    def_id: DefId,
+    symbol: &str,
+    linkage: Linkage,
+) -> LLVMValueRef {
+    let name = c_string!(format!("_rust_extern_with_linkage_{}", symbol));
+    let global = LLVMGetNamedGlobal(llmod, name.as_ptr());
+    if !global.is_null() {
+        return global;
+    }
+
+    let ty = tcx.type_of(def_id);
+    let pointee = match ty.kind() {
+        ty::RawPtr(mt) => mt.ty,
+        _ => tcx.sess.span_fatal(
+            tcx.def_span(def_id),
+            "must have type `*const T` or `*mut T` due to `#[linkage]` attribute",
+        ),
+    };
+
+    let symbol = c_string!(symbol);
+    let target = LLVMAddGlobal(llmod, ty_to_llvm_type(llcx, tcx, pointee), symbol.as_ptr());
+    LLVMSetLinkage(target, linkage_to_llvm(linkage));
+
+    let global = LLVMAddGlobal(llmod, ty_to_llvm_type(llcx, tcx, ty), name.as_ptr());
+    LLVMSetLinkage(global, LLVMLinkage::LLVMInternalLinkage);
+    LLVMSetInitializer(global, target);
+    global
+}
+
+fn linkage_to_llvm(linkage: Linkage) -> LLVMLinkage {
+    match linkage {
+        Linkage::External => LLVMLinkage::LLVMExternalLinkage,
+        Linkage::AvailableExternally => LLVMLinkage::LLVMAvailableExternallyLinkage,
+        Linkage::LinkOnceAny => LLVMLinkage::LLVMLinkOnceAnyLinkage,
+        Linkage::LinkOnceODR => LLVMLinkage::LLVMLinkOnceODRLinkage,
+        Linkage::WeakAny => LLVMLinkage::LLVMWeakAnyLinkage,
+        Linkage::WeakODR => LLVMLinkage::LLVMWeakODRLinkage,
+        Linkage::Appending => LLVMLinkage::LLVMAppendingLinkage,
+        Linkage::Internal => LLVMLinkage::LLVMInternalLinkage,
+        Linkage::Private => LLVMLinkage::LLVMPrivateLinkage,
+        Linkage::ExternalWeak => LLVMLinkage::LLVMExternalWeakLinkage,
+        Linkage::Common => LLVMLinkage::LLVMCommonLinkage,
+    }
+}
+
+/// Offsets the constant `i8*` `ptr` by `offset` bytes.
+pub(crate) unsafe fn const_gep(
+    llcx: LLVMContextRef,
+    ptr: LLVMValueRef,
+    offset: Size,
+) -> LLVMValueRef {
+    if offset == Size::ZERO {
+        return ptr;
+    }
+
+    let mut indices = [LLVMConstInt(
+        LLVMInt64TypeInContext(llcx),
+        offset.bytes(),
+        0,
+    )];
+    LLVMConstInBoundsGEP2(
+        LLVMInt8TypeInContext(llcx),
+        ptr,
+        indices.as_mut_ptr(),
+        indices.len() as core::ffi::c_uint,
+    )
+}
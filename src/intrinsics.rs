@@ -0,0 +1,452 @@
+use rustc_middle::{
+    mir::{self, BinOp},
+    ty::{Instance, ParamEnv, Ty, TyKind},
+};
+
+use rustc_span::Span;
+
+use llvm_sys::{core::*, prelude::*, LLVMAtomicOrdering, LLVMAtomicRMWBinOp, LLVMTypeKind};
+
+use std::ffi::CString;
+
+use crate::{
+    c_string,
+    codegen::{get_fn, TPlace},
+    ty::{has_pointer_metadata, i8p_type, isize_type, ty_to_llvm_type},
+    FunctionCx,
+};
+
+impl<'tcx> FunctionCx<'tcx> {
+    /// Lowers a call to a compiler intrinsic, which has no MIR body of its own, and stores its
+    /// result in `dest`.
+    pub(crate) unsafe fn codegen_intrinsic_call(
+        &mut self,
+        instance: Instance<'tcx>,
+        operands: &[mir::Operand<'tcx>],
+        dest: TPlace<'tcx>,
+        span: Span,
+    ) {
+        let name = self.tcx.item_name(instance.def_id());
+        let args = operands
+            .iter()
+            .map(|arg| *self.codegen_operand(arg).load_scalar(self.llbx).llval())
+            .collect::<Vec<_>>();
+        let llname = c_string!("");
+
+        let llval = match name.as_str() {
+            "size_of_val" | "min_align_of_val" => {
+                let layout = self.layout_of(instance.substs.type_at(0));
+                let llextra = has_pointer_metadata(self.tcx, layout.ty)
+                    .then(|| LLVMBuildExtractValue(self.llbx, args[0], 1, llname.as_ptr()));
+                let (size, align) = self.size_and_align_of(layout, llextra);
+                if name.as_str() == "size_of_val" {
+                    size
+                } else {
+                    align
+                }
+            }
+            "ctpop" | "bswap" | "bitreverse" => {
+                let llty = LLVMTypeOf(args[0]);
+                // `llvm.bswap` is not defined for single bytes.
+                if name.as_str() == "bswap" && LLVMGetIntTypeWidth(llty) == 8 {
+                    args[0]
+                } else {
+                    self.call_intrinsic(&format!("llvm.{}", name), &[llty], &[args[0]])
+                }
+            }
+            "ctlz" | "ctlz_nonzero" | "cttz" | "cttz_nonzero" => {
+                let llty = LLVMTypeOf(args[0]);
+                let is_zero_poison = LLVMConstInt(
+                    LLVMInt1TypeInContext(self.llcx),
+                    name.as_str().ends_with("_nonzero") as u64,
+                    0,
+                );
+                let intrinsic = format!("llvm.{}", &name.as_str()[..4]);
+                let count = self.call_intrinsic(&intrinsic, &[llty], &[args[0], is_zero_poison]);
+                let dest_llty = ty_to_llvm_type(self.llcx, self.tcx, dest.ty());
+                LLVMBuildIntCast2(self.llbx, count, dest_llty, 0, llname.as_ptr())
+            }
+            // Rotations are funnel shifts of a value with itself.
+            "rotate_left" | "rotate_right" => {
+                let llty = LLVMTypeOf(args[0]);
+                let amount = LLVMBuildIntCast2(self.llbx, args[1], llty, 0, llname.as_ptr());
+                let intrinsic = if name.as_str() == "rotate_left" {
+                    "llvm.fshl"
+                } else {
+                    "llvm.fshr"
+                };
+                self.call_intrinsic(intrinsic, &[llty], &[args[0], args[0], amount])
+            }
+            "add_with_overflow" | "sub_with_overflow" | "mul_with_overflow" => {
+                let bin_op = bin_op_of(&name.as_str()[..3]);
+                let ty = instance.substs.type_at(0);
+                let (llval, overflow) = self.codegen_checked_binop(bin_op, args[0], args[1], ty);
+                self.store_pair(dest, llval, overflow);
+                return;
+            }
+            "saturating_add" | "saturating_sub" => {
+                let sign = if instance.substs.type_at(0).is_signed() {
+                    "s"
+                } else {
+                    "u"
+                };
+                let intrinsic = format!("llvm.{}{}.sat", sign, &name.as_str()[11..]);
+                self.call_intrinsic(&intrinsic, &[LLVMTypeOf(args[0])], &args)
+            }
+            "unchecked_add" | "unchecked_sub" | "unchecked_mul" => {
+                let build = match (name.as_str(), instance.substs.type_at(0).is_signed()) {
+                    ("unchecked_add", true) => LLVMBuildNSWAdd,
+                    ("unchecked_add", false) => LLVMBuildNUWAdd,
+                    ("unchecked_sub", true) => LLVMBuildNSWSub,
+                    ("unchecked_sub", false) => LLVMBuildNUWSub,
+                    ("unchecked_mul", true) => LLVMBuildNSWMul,
+                    _ => LLVMBuildNUWMul,
+                };
+                build(self.llbx, args[0], args[1], llname.as_ptr())
+            }
+            "wrapping_add" | "wrapping_sub" | "wrapping_mul" | "unchecked_div"
+            | "unchecked_rem" | "unchecked_shl" | "unchecked_shr" => {
+                let bin_op = bin_op_of(name.as_str().split('_').nth(1).unwrap());
+                let ty = instance.substs.type_at(0);
+                self.codegen_scalar_binop(bin_op, args[0], args[1], ty)
+            }
+            "exact_div" => {
+                if instance.substs.type_at(0).is_signed() {
+                    LLVMBuildExactSDiv(self.llbx, args[0], args[1], llname.as_ptr())
+                } else {
+                    LLVMBuildExactUDiv(self.llbx, args[0], args[1], llname.as_ptr())
+                }
+            }
+            // Unlike `offset`, the result may leave the allocation, so the GEP is not `inbounds`.
+            "arith_offset" => {
+                let pointee = ty_to_llvm_type(self.llcx, self.tcx, instance.substs.type_at(0));
+                let mut indices = [args[1]];
+                LLVMBuildGEP2(
+                    self.llbx,
+                    pointee,
+                    args[0],
+                    indices.as_mut_ptr(),
+                    indices.len() as core::ffi::c_uint,
+                    llname.as_ptr(),
+                )
+            }
+            // Code that runs at all has left const evaluation, so the runtime function is called
+            // with the fields of the argument tuple.
+            "const_eval_select" => {
+                let (func_id, substs) = match *instance.substs.type_at(2).kind() {
+                    TyKind::FnDef(func_id, substs) => (func_id, substs),
+                    _ => unreachable!("`const_eval_select` of a runtime function that is no item"),
+                };
+                let func = Instance::resolve(self.tcx, ParamEnv::reveal_all(), func_id, substs)
+                    .unwrap()
+                    .unwrap();
+                let func = get_fn(self.llcx, self.llmod, self.tcx, func);
+                let tupled = self.codegen_operand(&operands[0]);
+                let mut func_args = Vec::new();
+                for idx in 0..tupled.layout().fields.count() {
+                    let arg = self.project_field(tupled.clone(), idx);
+                    if !arg.layout().is_zst() {
+                        func_args.push(*arg.load_scalar(self.llbx).llval());
+                    }
+                }
+                LLVMBuildCall2(
+                    self.llbx,
+                    LLVMGetElementType(LLVMTypeOf(func)),
+                    func,
+                    func_args.as_mut_ptr(),
+                    func_args.len() as u32,
+                    llname.as_ptr(),
+                )
+            }
+            "caller_location" => self.get_caller_location(span),
+            "likely" | "unlikely" => {
+                let i1 = LLVMInt1TypeInContext(self.llcx);
+                let expected = LLVMConstInt(i1, (name.as_str() == "likely") as u64, 0);
+                self.call_intrinsic("llvm.expect", &[i1], &[args[0], expected])
+            }
+            // The call has no target, so the block is ended with `unreachable` by the caller.
+            "abort" => {
+                self.call_intrinsic("llvm.trap", &[], &[]);
+                return;
+            }
+            // These only depend on the type they are instantiated with, so the interpreter can
+            // evaluate them.
+            "size_of" | "min_align_of" | "pref_align_of" | "needs_drop" | "type_id"
+            | "type_name" => {
+                let val = self
+                    .tcx
+                    .const_eval_instance(ParamEnv::reveal_all(), instance, None)
+                    .unwrap();
+                let val = self.codegen_const_value(dest.layout(), val);
+                *val.load_scalar(self.llbx).llval()
+            }
+            "copy" | "copy_nonoverlapping" => {
+                let ty = instance.substs.type_at(0);
+                let nonoverlapping = name.as_str() == "copy_nonoverlapping";
+                self.codegen_copy(ty, args[0], args[1], args[2], nonoverlapping);
+                return;
+            }
+            "write_bytes" => {
+                let layout = self.layout_of(instance.substs.type_at(0));
+                let dst =
+                    LLVMBuildBitCast(self.llbx, args[0], i8p_type(self.llcx), llname.as_ptr());
+                let size = LLVMConstInt(isize_type(self.llcx, self.tcx), layout.size.bytes(), 0);
+                let size = LLVMBuildMul(self.llbx, args[2], size, llname.as_ptr());
+                LLVMBuildMemSet(
+                    self.llbx,
+                    dst,
+                    args[1],
+                    size,
+                    layout.align.abi.bytes() as core::ffi::c_uint,
+                );
+                return;
+            }
+            "volatile_load" | "unaligned_volatile_load" => {
+                let layout = self.layout_of(instance.substs.type_at(0));
+                let llty = ty_to_llvm_type(self.llcx, self.tcx, layout.ty);
+                let load = LLVMBuildLoad2(self.llbx, llty, args[0], llname.as_ptr());
+                LLVMSetVolatile(load, 1);
+                if name.as_str() == "unaligned_volatile_load" {
+                    LLVMSetAlignment(load, 1);
+                }
+                load
+            }
+            "volatile_store" | "unaligned_volatile_store" => {
+                let store = LLVMBuildStore(self.llbx, args[1], args[0]);
+                LLVMSetVolatile(store, 1);
+                if name.as_str() == "unaligned_volatile_store" {
+                    LLVMSetAlignment(store, 1);
+                }
+                return;
+            }
+            // The value is reinterpreted by storing it through a pointer to its own type.
+            "transmute" => {
+                if !dest.layout().is_zst() {
+                    let ptr = LLVMBuildBitCast(
+                        self.llbx,
+                        *dest.llval(),
+                        LLVMPointerType(LLVMTypeOf(args[0]), 0),
+                        llname.as_ptr(),
+                    );
+                    let store = LLVMBuildStore(self.llbx, args[0], ptr);
+                    LLVMSetAlignment(store, dest.align().bytes() as core::ffi::c_uint);
+                }
+                return;
+            }
+            float_name if float_intrinsic(float_name).is_some() => {
+                let intrinsic = float_intrinsic(float_name).unwrap();
+                // `llvm.powi` is also overloaded on the type of its exponent.
+                let overloads = if intrinsic == "llvm.powi" { 2 } else { 1 };
+                let types = args[..overloads]
+                    .iter()
+                    .map(|arg| LLVMTypeOf(*arg))
+                    .collect::<Vec<_>>();
+                self.call_intrinsic(intrinsic, &types, &args)
+            }
+            simd_name if simd_name.starts_with("simd_") => {
+                self.codegen_simd_intrinsic(simd_name, instance, operands, &args, dest);
+                return;
+            }
+            atomic_name if atomic_name.starts_with("atomic_") => {
+                self.codegen_atomic_intrinsic(atomic_name, instance, &args, dest);
+                return;
+            }
+            _ => todo!("intrinsic `{}`", name),
+        };
+
+        dest.store_llval(self.llbx, llval);
+    }
+}
+
+impl<'tcx> FunctionCx<'tcx> {
+    /// Copies `count` values of type `ty` from `src` to `dst`, through `llvm.memmove` unless the
+    /// two are known not to overlap.
+    pub(crate) unsafe fn codegen_copy(
+        &mut self,
+        ty: Ty<'tcx>,
+        src: LLVMValueRef,
+        dst: LLVMValueRef,
+        count: LLVMValueRef,
+        nonoverlapping: bool,
+    ) {
+        let name = c_string!("");
+        let layout = self.layout_of(ty);
+        let align = layout.align.abi.bytes() as core::ffi::c_uint;
+        let i8p = i8p_type(self.llcx);
+        let src = LLVMBuildBitCast(self.llbx, src, i8p, name.as_ptr());
+        let dst = LLVMBuildBitCast(self.llbx, dst, i8p, name.as_ptr());
+        let size = LLVMConstInt(isize_type(self.llcx, self.tcx), layout.size.bytes(), 0);
+        let size = LLVMBuildMul(self.llbx, count, size, name.as_ptr());
+
+        if nonoverlapping {
+            LLVMBuildMemCpy(self.llbx, dst, align, src, align, size);
+        } else {
+            LLVMBuildMemMove(self.llbx, dst, align, src, align, size);
+        }
+    }
+}
+
+impl<'tcx> FunctionCx<'tcx> {
+    /// Lowers `atomic_<op>_<ordering>` intrinsics, e.g. `atomic_xadd_seqcst`. Compare-exchanges
+    /// carry an ordering for success and one for failure, e.g. `atomic_cxchg_acqrel_acquire`.
+    unsafe fn codegen_atomic_intrinsic(
+        &mut self,
+        name: &str,
+        instance: Instance<'tcx>,
+        args: &[LLVMValueRef],
+        dest: TPlace<'tcx>,
+    ) {
+        let llname = c_string!("");
+        let mut parts = name.split('_').skip(1);
+        let op = parts.next().unwrap();
+        let ordering = parts.next().map(atomic_ordering);
+        let single_thread = (op == "singlethreadfence") as LLVMBool;
+
+        let llval = match op {
+            "fence" | "singlethreadfence" => {
+                LLVMBuildFence(self.llbx, ordering.unwrap(), single_thread, llname.as_ptr());
+                return;
+            }
+            "load" => {
+                let layout = self.layout_of(instance.substs.type_at(0));
+                let llty = ty_to_llvm_type(self.llcx, self.tcx, layout.ty);
+                let load = LLVMBuildLoad2(self.llbx, llty, args[0], llname.as_ptr());
+                LLVMSetOrdering(load, ordering.unwrap());
+                LLVMSetAlignment(load, layout.size.bytes() as core::ffi::c_uint);
+                load
+            }
+            "store" => {
+                let layout = self.layout_of(instance.substs.type_at(0));
+                let store = LLVMBuildStore(self.llbx, args[1], args[0]);
+                LLVMSetOrdering(store, ordering.unwrap());
+                LLVMSetAlignment(store, layout.size.bytes() as core::ffi::c_uint);
+                return;
+            }
+            "cxchg" | "cxchgweak" => {
+                let failure = atomic_ordering(parts.next().unwrap());
+                let pair = LLVMBuildAtomicCmpXchg(
+                    self.llbx,
+                    args[0],
+                    args[1],
+                    args[2],
+                    ordering.unwrap(),
+                    failure,
+                    single_thread,
+                );
+                LLVMSetWeak(pair, (op == "cxchgweak") as LLVMBool);
+                let llval = LLVMBuildExtractValue(self.llbx, pair, 0, llname.as_ptr());
+                let success = LLVMBuildExtractValue(self.llbx, pair, 1, llname.as_ptr());
+                self.store_pair(dest, llval, success);
+                return;
+            }
+            _ => {
+                let rmw_op = match op {
+                    "xchg" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpXchg,
+                    "xadd" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpAdd,
+                    "xsub" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpSub,
+                    "and" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpAnd,
+                    "nand" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpNand,
+                    "or" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpOr,
+                    "xor" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpXor,
+                    "max" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpMax,
+                    "min" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpMin,
+                    "umax" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpUMax,
+                    "umin" => LLVMAtomicRMWBinOp::LLVMAtomicRMWBinOpUMin,
+                    _ => todo!("intrinsic `{}`", name),
+                };
+
+                // `atomicrmw` only operates on integers, so pointers go through `usize`.
+                let llty = LLVMTypeOf(args[1]);
+                if LLVMGetTypeKind(llty) == LLVMTypeKind::LLVMPointerTypeKind {
+                    let usize_llty = isize_type(self.llcx, self.tcx);
+                    let ptr = LLVMBuildBitCast(
+                        self.llbx,
+                        args[0],
+                        LLVMPointerType(usize_llty, 0),
+                        llname.as_ptr(),
+                    );
+                    let val = LLVMBuildPtrToInt(self.llbx, args[1], usize_llty, llname.as_ptr());
+                    let old = LLVMBuildAtomicRMW(
+                        self.llbx,
+                        rmw_op,
+                        ptr,
+                        val,
+                        ordering.unwrap(),
+                        single_thread,
+                    );
+                    LLVMBuildIntToPtr(self.llbx, old, llty, llname.as_ptr())
+                } else {
+                    LLVMBuildAtomicRMW(
+                        self.llbx,
+                        rmw_op,
+                        args[0],
+                        args[1],
+                        ordering.unwrap(),
+                        single_thread,
+                    )
+                }
+            }
+        };
+
+        dest.store_llval(self.llbx, llval);
+    }
+}
+
+fn atomic_ordering(ordering: &str) -> LLVMAtomicOrdering {
+    match ordering {
+        "unordered" => LLVMAtomicOrdering::LLVMAtomicOrderingUnordered,
+        "relaxed" => LLVMAtomicOrdering::LLVMAtomicOrderingMonotonic,
+        "acquire" => LLVMAtomicOrdering::LLVMAtomicOrderingAcquire,
+        "release" => LLVMAtomicOrdering::LLVMAtomicOrderingRelease,
+        "acqrel" => LLVMAtomicOrdering::LLVMAtomicOrderingAcquireRelease,
+        "seqcst" => LLVMAtomicOrdering::LLVMAtomicOrderingSequentiallyConsistent,
+        _ => unreachable!("unknown atomic ordering `{}`", ordering),
+    }
+}
+
+/// Returns the LLVM intrinsic for a floating-point intrinsic like `sqrtf64`, which has a variant
+/// for each float type.
+fn float_intrinsic(name: &str) -> Option<&'static str> {
+    let name = name
+        .strip_suffix("f32")
+        .or_else(|| name.strip_suffix("f64"))?;
+    let intrinsic = match name {
+        "sqrt" => "llvm.sqrt",
+        "powi" => "llvm.powi",
+        "sin" => "llvm.sin",
+        "cos" => "llvm.cos",
+        "pow" => "llvm.pow",
+        "exp" => "llvm.exp",
+        "exp2" => "llvm.exp2",
+        "log" => "llvm.log",
+        "log10" => "llvm.log10",
+        "log2" => "llvm.log2",
+        "fma" => "llvm.fma",
+        "fabs" => "llvm.fabs",
+        "minnum" => "llvm.minnum",
+        "maxnum" => "llvm.maxnum",
+        "copysign" => "llvm.copysign",
+        "floor" => "llvm.floor",
+        "ceil" => "llvm.ceil",
+        "trunc" => "llvm.trunc",
+        "rint" => "llvm.rint",
+        "nearbyint" => "llvm.nearbyint",
+        "round" => "llvm.round",
+        _ => return None,
+    };
+
+    Some(intrinsic)
+}
+
+fn bin_op_of(op: &str) -> BinOp {
+    match op {
+        "add" => BinOp::Add,
+        "sub" => BinOp::Sub,
+        "mul" => BinOp::Mul,
+        "div" => BinOp::Div,
+        "rem" => BinOp::Rem,
+        "shl" => BinOp::Shl,
+        "shr" => BinOp::Shr,
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,248 @@
+use rustc_session::Session;
+
+use llvm_sys::{
+    core::*,
+    prelude::*,
+    support::{LLVMAddSymbol, LLVMLoadLibraryPermanently, LLVMSearchForAddressOfSymbol},
+    target::{LLVMGetModuleDataLayout, LLVMIntPtrTypeInContext},
+    LLVMOpcode,
+};
+
+use std::{
+    alloc::Layout,
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{c_void, CString},
+};
+
+use crate::c_string;
+
+/// Makes the symbols of the host process visible to MCJIT, along with the compiler-builtins and
+/// libm functions that it may not export.
+pub(crate) unsafe fn register_symbols() {
+    // A null filename loads the symbols of the process itself, which includes libc.
+    LLVMLoadLibraryPermanently(std::ptr::null());
+
+    for (name, addr) in builtins() {
+        LLVMAddSymbol(c_string!(name).as_ptr(), addr as *mut c_void);
+    }
+
+    // Thread-local destructors are registered against the object defining them, which the host
+    // does not export.
+    extern "C" {
+        static __dso_handle: u8;
+    }
+    LLVMAddSymbol(
+        c_string!("__dso_handle").as_ptr(),
+        &__dso_handle as *const u8 as *mut c_void,
+    );
+}
+
+/// Returns the functions and globals declared in `llmod` which no loaded library defines. MCJIT
+/// would otherwise only report them once it aborts while finalizing the module.
+pub(crate) unsafe fn unresolved_symbols(llmod: LLVMModuleRef) -> Vec<String> {
+    let mut declarations = Vec::new();
+
+    let mut llfn = LLVMGetFirstFunction(llmod);
+    while !llfn.is_null() {
+        // Intrinsics are lowered by LLVM itself.
+        if LLVMIsDeclaration(llfn) != 0 && LLVMGetIntrinsicID(llfn) == 0 {
+            declarations.push(llfn);
+        }
+        llfn = LLVMGetNextFunction(llfn);
+    }
+    let mut global = LLVMGetFirstGlobal(llmod);
+    while !global.is_null() {
+        if LLVMIsDeclaration(global) != 0 {
+            declarations.push(global);
+        }
+        global = LLVMGetNextGlobal(global);
+    }
+
+    declarations
+        .into_iter()
+        .filter_map(|llval| {
+            let mut len = 0;
+            let name = LLVMGetValueName2(llval, &mut len);
+            let name = std::slice::from_raw_parts(name as *const u8, len);
+            let name = CString::new(name).unwrap();
+            if LLVMSearchForAddressOfSymbol(name.as_ptr()).is_null() {
+                Some(name.into_string().unwrap())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The host function which hands out the thread-local storage of JIT'd code.
+const TLS_GET_ADDR: &str = "__tachyon_tls_get_addr";
+
+/// MCJIT cannot allocate TLS, so every access to a `thread_local` global is replaced by a call to
+/// [`tls_get_addr`], which returns the calling thread's copy of it. The global itself is left with
+/// the initial value of every copy.
+pub(crate) unsafe fn emulate_thread_locals(llmod: LLVMModuleRef, sess: &Session) {
+    let llcx = LLVMGetModuleContext(llmod);
+    let i8p = LLVMPointerType(LLVMInt8TypeInContext(llcx), 0);
+    let usize_llty = LLVMIntPtrTypeInContext(llcx, LLVMGetModuleDataLayout(llmod));
+    let mut params = [i8p, usize_llty, usize_llty];
+    let get_addr_ty = LLVMFunctionType(i8p, params.as_mut_ptr(), params.len() as _, 0);
+    let name = c_string!(TLS_GET_ADDR);
+    let mut get_addr = LLVMGetNamedFunction(llmod, name.as_ptr());
+
+    let llbx = LLVMCreateBuilderInContext(llcx);
+    let mut global = LLVMGetFirstGlobal(llmod);
+    while !global.is_null() {
+        if LLVMIsThreadLocal(global) == 0 || LLVMIsDeclaration(global) != 0 {
+            global = LLVMGetNextGlobal(global);
+            continue;
+        }
+        if get_addr.is_null() {
+            get_addr = LLVMAddFunction(llmod, name.as_ptr(), get_addr_ty);
+        }
+
+        LLVMSetThreadLocal(global, 0);
+        let llty = LLVMTypeOf(global);
+        let mut args = [
+            LLVMConstBitCast(global, i8p),
+            LLVMConstIntCast(LLVMSizeOf(LLVMGlobalGetValueType(global)), usize_llty, 0),
+            LLVMConstInt(usize_llty, LLVMGetAlignment(global) as u64, 0),
+        ];
+        let mut len = 0;
+        let name = LLVMGetValueName2(global, &mut len);
+        let name = String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, len));
+        replace_uses(sess, &name, llbx, global, &mut |llbx| {
+            let llname = c_string!("");
+            let addr = LLVMBuildCall2(
+                llbx,
+                get_addr_ty,
+                get_addr,
+                args.as_mut_ptr(),
+                args.len() as core::ffi::c_uint,
+                llname.as_ptr(),
+            );
+            LLVMBuildBitCast(llbx, addr, llty, llname.as_ptr())
+        });
+        global = LLVMGetNextGlobal(global);
+    }
+    LLVMDisposeBuilder(llbx);
+}
+
+/// Replaces the uses of `old` in instructions by the value `build` emits right before each of
+/// them. Constant expressions using `old` are rebuilt as instructions on top of that value; any
+/// other user of the thread-local `name` is reported as an error.
+unsafe fn replace_uses(
+    sess: &Session,
+    name: &str,
+    llbx: LLVMBuilderRef,
+    old: LLVMValueRef,
+    build: &mut dyn FnMut(LLVMBuilderRef) -> LLVMValueRef,
+) {
+    let mut users = Vec::new();
+    let mut use_ = LLVMGetFirstUse(old);
+    while !use_.is_null() {
+        users.push(LLVMGetUser(use_));
+        use_ = LLVMGetNextUse(use_);
+    }
+    users.sort();
+    users.dedup();
+
+    for user in users {
+        if !LLVMIsAInstruction(user).is_null() {
+            LLVMPositionBuilderBefore(llbx, user);
+            let new = build(llbx);
+            for idx in 0..LLVMGetNumOperands(user) as core::ffi::c_uint {
+                if LLVMGetOperand(user, idx) == old {
+                    LLVMSetOperand(user, idx, new);
+                }
+            }
+        } else if !LLVMIsAConstantExpr(user).is_null() {
+            let llname = c_string!("");
+            let llty = LLVMTypeOf(user);
+            match LLVMGetConstOpcode(user) {
+                LLVMOpcode::LLVMBitCast => replace_uses(sess, name, llbx, user, &mut |llbx| {
+                    LLVMBuildBitCast(llbx, build(llbx), llty, llname.as_ptr())
+                }),
+                LLVMOpcode::LLVMGetElementPtr => {
+                    let elem_llty = LLVMGetGEPSourceElementType(user);
+                    let mut indices = (1..LLVMGetNumOperands(user) as core::ffi::c_uint)
+                        .map(|idx| LLVMGetOperand(user, idx))
+                        .collect::<Vec<_>>();
+                    replace_uses(sess, name, llbx, user, &mut |llbx| {
+                        LLVMBuildInBoundsGEP2(
+                            llbx,
+                            elem_llty,
+                            build(llbx),
+                            indices.as_mut_ptr(),
+                            indices.len() as core::ffi::c_uint,
+                            llname.as_ptr(),
+                        )
+                    })
+                }
+                opcode => sess.err(&format!(
+                    "cannot emulate the thread-local `{}` accessed through a `{:?}` constant",
+                    name, opcode
+                )),
+            }
+        } else if !LLVMIsAGlobalVariable(user).is_null() {
+            sess.err(&format!(
+                "cannot emulate the thread-local `{}` used in the initializer of a global",
+                name
+            ));
+        } else {
+            sess.err(&format!(
+                "cannot emulate the thread-local `{}` used in a constant",
+                name
+            ));
+        }
+    }
+}
+
+/// Returns the calling thread's copy of the thread-local whose initial value is at `image`.
+///
+/// Copies are never freed, since the destructors that JIT'd code registers for its thread-locals
+/// may still run after the map is gone.
+extern "C" fn tls_get_addr(image: *const u8, size: usize, align: usize) -> *mut u8 {
+    thread_local! {
+        static COPIES: RefCell<HashMap<*const u8, *mut u8>> = RefCell::new(HashMap::new());
+    }
+
+    COPIES.with(|copies| {
+        *copies.borrow_mut().entry(image).or_insert_with(|| unsafe {
+            let copy = std::alloc::alloc(Layout::from_size_align(size.max(1), align).unwrap());
+            std::ptr::copy_nonoverlapping(image, copy, size);
+            copy
+        })
+    })
+}
+
+/// Lists the functions that LLVM lowers some operations to along with their addresses, e.g. the
+/// 128-bit arithmetic and `powi` of compiler-builtins and the math functions of libm. The process
+/// links them in without exporting them, so they are declared here to take their addresses.
+macro_rules! builtins {
+    ($($name:ident),* $(,)?) => {
+        fn builtins() -> Vec<(&'static str, *const ())> {
+            extern "C" {
+                $(fn $name();)*
+            }
+
+            vec![
+                (TLS_GET_ADDR, tls_get_addr as *const ()),
+                $((stringify!($name), $name as *const ()),)*
+            ]
+        }
+    };
+}
+
+builtins! {
+    __ashlti3, __ashrti3, __lshrti3,
+    __divti3, __udivti3, __modti3, __umodti3, __udivmodti4,
+    __multi3, __mulosi4, __mulodi4, __muloti4,
+    __floattisf, __floattidf, __floatuntisf, __floatuntidf,
+    __fixsfti, __fixdfti, __fixunssfti, __fixunsdfti,
+    __powisf2, __powidf2,
+    fmod, fmodf, fma, fmaf,
+    sin, sinf, cos, cosf, pow, powf,
+    exp, expf, exp2, exp2f, log, logf, log2, log2f, log10, log10f,
+    floor, floorf, ceil, ceilf, trunc, truncf, rint, rintf, nearbyint, nearbyintf, round, roundf,
+}
@@ -2,18 +2,35 @@
 #![feature(once_cell)]
 #![feature(box_patterns)]
 
+extern crate rustc_ast;
 extern crate rustc_const_eval;
 extern crate rustc_hir;
 extern crate rustc_index;
 extern crate rustc_middle;
+extern crate rustc_session;
+extern crate rustc_span;
+extern crate rustc_target;
 
-use rustc_index::vec::IndexVec;
-use rustc_middle::{mir, ty::TyCtxt};
+use rustc_index::{bit_set::BitSet, vec::IndexVec};
+use rustc_middle::{
+    mir,
+    ty::{layout::TyAndLayout, Instance, ParamEnv, Ty, TyCtxt, TypeFoldable},
+};
 
 use llvm_sys::{core::*, prelude::*};
 
+pub mod allocator;
+pub(crate) mod analyze;
+pub(crate) mod asm;
+pub(crate) mod attributes;
 pub mod codegen;
+pub(crate) mod consts;
+pub(crate) mod intrinsics;
+pub(crate) mod jit;
+pub mod passes;
+pub(crate) mod simd;
 pub(crate) mod ty;
+pub(crate) mod vtable;
 
 pub(crate) struct FunctionCx<'tcx> {
     pub(crate) llcx: LLVMContextRef,
@@ -22,10 +39,17 @@ pub(crate) struct FunctionCx<'tcx> {
     pub(crate) llfn: LLVMValueRef,
 
     pub(crate) tcx: TyCtxt<'tcx>,
+    pub(crate) instance: Instance<'tcx>,
     pub(crate) mir: &'tcx mir::Body<'tcx>,
 
     pub(crate) locals: IndexVec<mir::Local, codegen::TPlace<'tcx>>,
     pub(crate) basic_blocks: IndexVec<mir::BasicBlock, LLVMBasicBlockRef>,
+    /// The hidden `&'static Location` parameter of `#[track_caller]` functions.
+    pub(crate) caller_location: Option<LLVMValueRef>,
+    /// The blocks that lead to a panic or `Unreachable`, and the values branched on with `likely`
+    /// and `unlikely`. Together they weigh the branches to these blocks.
+    pub(crate) cold_blocks: BitSet<mir::BasicBlock>,
+    pub(crate) expected_values: IndexVec<mir::Local, Option<bool>>,
 }
 
 impl<'tcx> FunctionCx<'tcx> {
@@ -34,6 +58,7 @@ impl<'tcx> FunctionCx<'tcx> {
         llmod: LLVMModuleRef,
         llfn: LLVMValueRef,
         tcx: TyCtxt<'tcx>,
+        instance: Instance<'tcx>,
         mir: &'tcx mir::Body<'tcx>,
     ) -> FunctionCx<'tcx> {
         let llbx = LLVMCreateBuilderInContext(llcx);
@@ -44,9 +69,28 @@ impl<'tcx> FunctionCx<'tcx> {
             llbx,
             llfn,
             tcx,
+            instance,
             mir,
             locals: IndexVec::with_capacity(mir.local_decls.len()),
             basic_blocks: IndexVec::with_capacity(mir.basic_blocks.raw.len()),
+            caller_location: None,
+            cold_blocks: BitSet::new_empty(mir.basic_blocks.len()),
+            expected_values: IndexVec::new(),
         }
     }
+
+    pub(crate) fn monomorphize<T>(&self, value: T) -> T
+    where
+        T: TypeFoldable<'tcx> + Copy,
+    {
+        self.instance.subst_mir_and_normalize_erasing_regions(
+            self.tcx,
+            ParamEnv::reveal_all(),
+            value,
+        )
+    }
+
+    pub(crate) fn layout_of(&self, ty: Ty<'tcx>) -> TyAndLayout<'tcx> {
+        ty::layout_of(self.tcx, ty)
+    }
 }
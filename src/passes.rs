@@ -0,0 +1,126 @@
+use rustc_session::{config::OptLevel, Session};
+
+use llvm_sys::{
+    core::LLVMDisposeMessage,
+    error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage},
+    prelude::*,
+    support::LLVMParseCommandLineOptions,
+    target::LLVM_InitializeNativeTarget,
+    target_machine::{
+        LLVMCodeGenOptLevel, LLVMCodeModel, LLVMCreateTargetMachine, LLVMDisposeTargetMachine,
+        LLVMGetDefaultTargetTriple, LLVMGetHostCPUFeatures, LLVMGetHostCPUName,
+        LLVMGetTargetFromTriple, LLVMRelocMode, LLVMTargetMachineRef,
+    },
+    transforms::pass_builder::{
+        LLVMCreatePassBuilderOptions, LLVMDisposePassBuilderOptions,
+        LLVMPassBuilderOptionsSetLoopInterleaving, LLVMPassBuilderOptionsSetLoopVectorization,
+        LLVMPassBuilderOptionsSetSLPVectorization, LLVMRunPasses,
+    },
+};
+
+use std::{
+    ffi::{CStr, CString},
+    sync::Once,
+};
+
+use crate::c_string;
+
+/// Runs the LLVM pipeline matching `-C opt-level` over `llmod`, followed by the passes given with
+/// `-C passes`. Unoptimized modules are left as generated.
+pub unsafe fn run_passes(llmod: LLVMModuleRef, sess: &Session) {
+    configure_llvm(sess);
+
+    let pipeline = match sess.opts.optimize {
+        OptLevel::No => None,
+        OptLevel::Less => Some("default<O1>"),
+        OptLevel::Default => Some("default<O2>"),
+        OptLevel::Aggressive => Some("default<O3>"),
+        OptLevel::Size => Some("default<Os>"),
+        OptLevel::SizeMin => Some("default<Oz>"),
+    };
+    let mut pipelines = pipeline.map(String::from).into_iter().collect::<Vec<_>>();
+    // These run on their own, so that LLVM infers whether they are module or function passes.
+    if !sess.opts.cg.passes.is_empty() {
+        pipelines.push(sess.opts.cg.passes.join(","));
+    }
+    if pipelines.is_empty() {
+        return;
+    }
+
+    let tm = create_target_machine(sess);
+    let options = LLVMCreatePassBuilderOptions();
+    // Like rustc, only vectorize at `-C opt-level=2` and above.
+    let vectorize =
+        matches!(sess.opts.optimize, OptLevel::Default | OptLevel::Aggressive) as LLVMBool;
+    LLVMPassBuilderOptionsSetLoopVectorization(options, vectorize);
+    LLVMPassBuilderOptionsSetLoopInterleaving(options, vectorize);
+    LLVMPassBuilderOptionsSetSLPVectorization(options, vectorize);
+
+    for pipeline in pipelines {
+        let err = LLVMRunPasses(llmod, c_string!(pipeline.as_str()).as_ptr(), tm, options);
+        if !err.is_null() {
+            let msg = LLVMGetErrorMessage(err);
+            let err = CStr::from_ptr(msg).to_string_lossy().into_owned();
+            LLVMDisposeErrorMessage(msg);
+            sess.fatal(&format!(
+                "failed to run LLVM passes `{}`: {}",
+                pipeline, err
+            ));
+        }
+    }
+
+    LLVMDisposePassBuilderOptions(options);
+    LLVMDisposeTargetMachine(tm);
+}
+
+/// Passes `-C llvm-args` on to LLVM. Its options are global, so they are only parsed once.
+unsafe fn configure_llvm(sess: &Session) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let args = std::iter::once("tachyon")
+            .chain(sess.opts.cg.llvm_args.iter().map(String::as_str))
+            .map(|arg| c_string!(arg))
+            .collect::<Vec<_>>();
+        let argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+        LLVMParseCommandLineOptions(argv.len() as _, argv.as_ptr(), std::ptr::null());
+    });
+}
+
+/// Returns a target machine for the host, whose costs guide the vectorizers and the inliner.
+unsafe fn create_target_machine(sess: &Session) -> LLVMTargetMachineRef {
+    LLVM_InitializeNativeTarget();
+
+    let triple = LLVMGetDefaultTargetTriple();
+    let mut target = std::ptr::null_mut();
+    let mut err = std::ptr::null_mut();
+    if LLVMGetTargetFromTriple(triple, &mut target, &mut err) != 0 {
+        let msg = CStr::from_ptr(err).to_string_lossy().into_owned();
+        LLVMDisposeMessage(err);
+        sess.fatal(&format!("failed to look up the host target: {}", msg));
+    }
+
+    let level = match sess.opts.optimize {
+        OptLevel::No => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+        OptLevel::Less => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+        OptLevel::Default | OptLevel::Size | OptLevel::SizeMin => {
+            LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault
+        }
+        OptLevel::Aggressive => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+    };
+    let cpu = LLVMGetHostCPUName();
+    let features = LLVMGetHostCPUFeatures();
+    let tm = LLVMCreateTargetMachine(
+        target,
+        triple,
+        cpu,
+        features,
+        level,
+        LLVMRelocMode::LLVMRelocDefault,
+        LLVMCodeModel::LLVMCodeModelJITDefault,
+    );
+    LLVMDisposeMessage(triple);
+    LLVMDisposeMessage(cpu);
+    LLVMDisposeMessage(features);
+
+    tm
+}
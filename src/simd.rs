@@ -0,0 +1,209 @@
+use rustc_const_eval::interpret::{read_target_uint, ConstValue};
+use rustc_middle::{
+    mir::{self, BinOp},
+    ty::{Instance, ParamEnv, TyKind},
+};
+
+use llvm_sys::{core::*, prelude::*};
+
+use std::ffi::CString;
+
+use crate::{c_string, codegen::TPlace, ty::ty_to_llvm_type, FunctionCx};
+
+impl<'tcx> FunctionCx<'tcx> {
+    /// Lowers the generic `simd_*` platform intrinsics, which operate on `#[repr(simd)]` types
+    /// lowered to LLVM vectors.
+    pub(crate) unsafe fn codegen_simd_intrinsic(
+        &mut self,
+        name: &str,
+        instance: Instance<'tcx>,
+        operands: &[mir::Operand<'tcx>],
+        args: &[LLVMValueRef],
+        dest: TPlace<'tcx>,
+    ) {
+        let llname = c_string!("");
+        let (_, elem_ty) = instance.substs.type_at(0).simd_size_and_type(self.tcx);
+        let is_float = elem_ty.is_floating_point();
+        let is_signed = elem_ty.is_signed();
+        let dest_llty = ty_to_llvm_type(self.llcx, self.tcx, dest.ty());
+
+        let llval = match name {
+            "simd_add" | "simd_sub" | "simd_mul" | "simd_div" | "simd_rem" | "simd_and"
+            | "simd_or" | "simd_xor" => {
+                let bin_op = match name {
+                    "simd_add" => BinOp::Add,
+                    "simd_sub" => BinOp::Sub,
+                    "simd_mul" => BinOp::Mul,
+                    "simd_div" => BinOp::Div,
+                    "simd_rem" => BinOp::Rem,
+                    "simd_and" => BinOp::BitAnd,
+                    "simd_or" => BinOp::BitOr,
+                    _ => BinOp::BitXor,
+                };
+                self.codegen_scalar_binop(bin_op, args[0], args[1], elem_ty)
+            }
+            // Unlike `<<` and `>>`, these do not mask the shift amount.
+            "simd_shl" => LLVMBuildShl(self.llbx, args[0], args[1], llname.as_ptr()),
+            "simd_shr" if is_signed => LLVMBuildAShr(self.llbx, args[0], args[1], llname.as_ptr()),
+            "simd_shr" => LLVMBuildLShr(self.llbx, args[0], args[1], llname.as_ptr()),
+            "simd_neg" if is_float => LLVMBuildFNeg(self.llbx, args[0], llname.as_ptr()),
+            "simd_neg" => LLVMBuildNeg(self.llbx, args[0], llname.as_ptr()),
+            // Comparisons return a mask, whose lanes are all ones where the comparison holds.
+            "simd_eq" | "simd_ne" | "simd_lt" | "simd_le" | "simd_gt" | "simd_ge" => {
+                let bin_op = match name {
+                    "simd_eq" => BinOp::Eq,
+                    "simd_ne" => BinOp::Ne,
+                    "simd_lt" => BinOp::Lt,
+                    "simd_le" => BinOp::Le,
+                    "simd_gt" => BinOp::Gt,
+                    _ => BinOp::Ge,
+                };
+                let cmp = self.codegen_scalar_binop(bin_op, args[0], args[1], elem_ty);
+                LLVMBuildSExt(self.llbx, cmp, dest_llty, llname.as_ptr())
+            }
+            "simd_extract" => LLVMBuildExtractElement(self.llbx, args[0], args[1], llname.as_ptr()),
+            "simd_insert" => {
+                LLVMBuildInsertElement(self.llbx, args[0], args[2], args[1], llname.as_ptr())
+            }
+            shuffle if shuffle.starts_with("simd_shuffle") => {
+                let mask = match &operands[2] {
+                    mir::Operand::Constant(constant) => self.simd_shuffle_mask(constant),
+                    _ => unreachable!("shuffle indices must be constant"),
+                };
+                LLVMBuildShuffleVector(self.llbx, args[0], args[1], mask, llname.as_ptr())
+            }
+            "simd_cast" => {
+                let (_, dest_elem_ty) = dest.ty().simd_size_and_type(self.tcx);
+                match (is_float, dest_elem_ty.is_floating_point()) {
+                    (false, false) => LLVMBuildIntCast2(
+                        self.llbx,
+                        args[0],
+                        dest_llty,
+                        is_signed as LLVMBool,
+                        llname.as_ptr(),
+                    ),
+                    (false, true) if is_signed => {
+                        LLVMBuildSIToFP(self.llbx, args[0], dest_llty, llname.as_ptr())
+                    }
+                    (false, true) => {
+                        LLVMBuildUIToFP(self.llbx, args[0], dest_llty, llname.as_ptr())
+                    }
+                    (true, false) if dest_elem_ty.is_signed() => {
+                        LLVMBuildFPToSI(self.llbx, args[0], dest_llty, llname.as_ptr())
+                    }
+                    (true, false) => {
+                        LLVMBuildFPToUI(self.llbx, args[0], dest_llty, llname.as_ptr())
+                    }
+                    (true, true) => LLVMBuildFPCast(self.llbx, args[0], dest_llty, llname.as_ptr()),
+                }
+            }
+            reduce if reduce.starts_with("simd_reduce_") => {
+                self.codegen_simd_reduce(&reduce["simd_reduce_".len()..], args, is_float, is_signed)
+            }
+            _ => todo!("intrinsic `{}`", name),
+        };
+
+        dest.store_llval(self.llbx, llval);
+    }
+
+    /// Lowers `simd_reduce_<op>` to the matching `llvm.vector.reduce.*` intrinsic.
+    unsafe fn codegen_simd_reduce(
+        &mut self,
+        op: &str,
+        args: &[LLVMValueRef],
+        is_float: bool,
+        is_signed: bool,
+    ) -> LLVMValueRef {
+        let llname = c_string!("");
+        let vec_llty = LLVMTypeOf(args[0]);
+        let elem_llty = LLVMGetElementType(vec_llty);
+
+        match op {
+            // Float reductions are sequential from a start value, which is the identity of the
+            // operation for the unordered variants.
+            "add_ordered" | "add_unordered" | "mul_ordered" | "mul_unordered" if is_float => {
+                let (intrinsic, identity) = if op.starts_with("add") {
+                    ("llvm.vector.reduce.fadd", -0.0)
+                } else {
+                    ("llvm.vector.reduce.fmul", 1.0)
+                };
+                let start = if op.ends_with("_ordered") {
+                    args[1]
+                } else {
+                    LLVMConstReal(elem_llty, identity)
+                };
+                self.call_intrinsic(intrinsic, &[vec_llty], &[start, args[0]])
+            }
+            "add_ordered" | "add_unordered" | "mul_ordered" | "mul_unordered" => {
+                let is_add = op.starts_with("add");
+                let intrinsic = if is_add {
+                    "llvm.vector.reduce.add"
+                } else {
+                    "llvm.vector.reduce.mul"
+                };
+                let llval = self.call_intrinsic(intrinsic, &[vec_llty], &[args[0]]);
+                match (op.ends_with("_ordered"), is_add) {
+                    (true, true) => LLVMBuildAdd(self.llbx, args[1], llval, llname.as_ptr()),
+                    (true, false) => LLVMBuildMul(self.llbx, args[1], llval, llname.as_ptr()),
+                    (false, _) => llval,
+                }
+            }
+            "and" | "or" | "xor" => {
+                let intrinsic = format!("llvm.vector.reduce.{}", op);
+                self.call_intrinsic(&intrinsic, &[vec_llty], &[args[0]])
+            }
+            "min" | "max" => {
+                let prefix = match (is_float, is_signed) {
+                    (true, _) => "f",
+                    (false, true) => "s",
+                    (false, false) => "u",
+                };
+                let intrinsic = format!("llvm.vector.reduce.{}{}", prefix, op);
+                self.call_intrinsic(&intrinsic, &[vec_llty], &[args[0]])
+            }
+            // Masks are truncated to their lowest bit, which is set exactly when all bits are.
+            "all" | "any" => {
+                let len = LLVMGetVectorSize(vec_llty);
+                let mask_llty = LLVMVectorType(LLVMInt1TypeInContext(self.llcx), len);
+                let mask = LLVMBuildTrunc(self.llbx, args[0], mask_llty, llname.as_ptr());
+                let intrinsic = if op == "all" {
+                    "llvm.vector.reduce.and"
+                } else {
+                    "llvm.vector.reduce.or"
+                };
+                self.call_intrinsic(intrinsic, &[mask_llty], &[mask])
+            }
+            _ => todo!("intrinsic `simd_reduce_{}`", op),
+        }
+    }
+
+    /// Reads the constant array of lane indices passed to `simd_shuffle` into a vector mask.
+    unsafe fn simd_shuffle_mask(&mut self, constant: &mir::Constant<'tcx>) -> LLVMValueRef {
+        let ty = self.monomorphize(constant.ty());
+        let len = match ty.kind() {
+            TyKind::Array(_, len) => len.eval_usize(self.tcx, ParamEnv::reveal_all()) as usize,
+            _ => unreachable!(),
+        };
+        let literal = self
+            .monomorphize(constant.literal)
+            .eval(self.tcx, ParamEnv::reveal_all());
+        let (alloc, offset) = match literal {
+            mir::ConstantKind::Val(ConstValue::ByRef { alloc, offset }, _) => (alloc, offset),
+            _ => unreachable!(),
+        };
+
+        let start = offset.bytes_usize();
+        let bytes = alloc
+            .inner()
+            .inspect_with_uninit_and_ptr_outside_interpreter(start..start + len * 4);
+        let i32_llty = LLVMInt32TypeInContext(self.llcx);
+        let mut indices = bytes
+            .chunks(4)
+            .map(|idx| {
+                let idx = read_target_uint(self.tcx.data_layout.endian, idx).unwrap();
+                LLVMConstInt(i32_llty, idx as u64, 0)
+            })
+            .collect::<Vec<_>>();
+        LLVMConstVector(indices.as_mut_ptr(), indices.len() as core::ffi::c_uint)
+    }
+}
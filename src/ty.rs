@@ -1,13 +1,53 @@
-use rustc_middle::ty::{FloatTy, FnSig, IntTy, Ty, TyKind, UintTy};
+use rustc_hir::{LangItem, Unsafety};
+use rustc_middle::ty::{
+    self,
+    layout::{LayoutCx, TyAndLayout},
+    subst::GenericArgKind,
+    FloatTy, FnSig, Instance, InstanceDef, IntTy, ParamEnv, Ty, TyCtxt, TyKind, UintTy,
+};
+use rustc_target::{
+    abi::{Abi, FieldsShape, Size, Variants},
+    spec::abi::Abi as FnAbi,
+};
 
-use llvm_sys::{core::*, prelude::*};
+use llvm_sys::{core::*, prelude::*, LLVMTypeKind};
 
-pub(crate) unsafe fn ty_to_llvm_type<'tcx>(llcx: LLVMContextRef, ty: Ty<'tcx>) -> LLVMTypeRef {
+use std::ffi::CString;
+
+use crate::c_string;
+
+pub(crate) fn layout_of<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> TyAndLayout<'tcx> {
+    tcx.layout_of(ParamEnv::reveal_all().and(ty)).unwrap()
+}
+
+pub(crate) fn layout_cx<'tcx>(tcx: TyCtxt<'tcx>) -> LayoutCx<'tcx, TyCtxt<'tcx>> {
+    LayoutCx {
+        tcx,
+        param_env: ParamEnv::reveal_all(),
+    }
+}
+
+pub(crate) unsafe fn isize_type<'tcx>(llcx: LLVMContextRef, tcx: TyCtxt<'tcx>) -> LLVMTypeRef {
+    LLVMIntTypeInContext(
+        llcx,
+        tcx.data_layout.pointer_size.bits() as core::ffi::c_uint,
+    )
+}
+
+pub(crate) unsafe fn i8p_type(llcx: LLVMContextRef) -> LLVMTypeRef {
+    LLVMPointerType(LLVMInt8TypeInContext(llcx), 0)
+}
+
+pub(crate) unsafe fn ty_to_llvm_type<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+) -> LLVMTypeRef {
     match ty.kind() {
         TyKind::Bool => LLVMInt1TypeInContext(llcx),
         TyKind::Char => LLVMInt32TypeInContext(llcx),
         TyKind::Int(int) => match int {
-            IntTy::Isize => todo!(),
+            IntTy::Isize => isize_type(llcx, tcx),
             IntTy::I8 => LLVMInt8TypeInContext(llcx),
             IntTy::I16 => LLVMInt16TypeInContext(llcx),
             IntTy::I32 => LLVMInt32TypeInContext(llcx),
@@ -15,7 +55,7 @@ pub(crate) unsafe fn ty_to_llvm_type<'tcx>(llcx: LLVMContextRef, ty: Ty<'tcx>) -
             IntTy::I128 => LLVMInt128TypeInContext(llcx),
         },
         TyKind::Uint(uint) => match uint {
-            UintTy::Usize => todo!(),
+            UintTy::Usize => isize_type(llcx, tcx),
             UintTy::U8 => LLVMInt8TypeInContext(llcx),
             UintTy::U16 => LLVMInt16TypeInContext(llcx),
             UintTy::U32 => LLVMInt32TypeInContext(llcx),
@@ -27,20 +67,243 @@ pub(crate) unsafe fn ty_to_llvm_type<'tcx>(llcx: LLVMContextRef, ty: Ty<'tcx>) -
             FloatTy::F64 => LLVMDoubleTypeInContext(llcx),
         },
         TyKind::Tuple(tuple) if tuple.len() == 0 => LLVMVoidTypeInContext(llcx),
+        // Values of `!` are never produced, so it only appears as the return type of functions.
+        TyKind::Never => LLVMVoidTypeInContext(llcx),
+        TyKind::Ref(_, pointee, _) | TyKind::RawPtr(ty::TypeAndMut { ty: pointee, .. }) => {
+            pointer_to_llvm_type(llcx, tcx, *pointee)
+        }
+        TyKind::Adt(..) if ty.is_box() => pointer_to_llvm_type(llcx, tcx, ty.boxed_ty()),
+        TyKind::FnPtr(fn_sig) => {
+            let fn_sig = tcx.normalize_erasing_late_bound_regions(ParamEnv::reveal_all(), *fn_sig);
+            LLVMPointerType(fn_sig_to_llvm_fn_type(llcx, tcx, fn_sig), 0)
+        }
+        TyKind::Array(elem, len) => LLVMArrayType(
+            ty_to_llvm_type(llcx, tcx, *elem),
+            len.eval_usize(tcx, ParamEnv::reveal_all()) as core::ffi::c_uint,
+        ),
+        // Slices are accessed through a pointer to their first element.
+        TyKind::Slice(elem) => ty_to_llvm_type(llcx, tcx, *elem),
+        TyKind::Str => LLVMInt8TypeInContext(llcx),
+        // Trait objects are only ever accessed through their vtable, so an opaque byte is enough.
+        TyKind::Dynamic(..) => LLVMInt8TypeInContext(llcx),
+        // Extern types have no known layout, and are only ever accessed through pointers.
+        TyKind::Foreign(..) => LLVMInt8TypeInContext(llcx),
+        TyKind::Adt(..) if ty.is_simd() => {
+            let (len, elem) = ty.simd_size_and_type(tcx);
+            LLVMVectorType(ty_to_llvm_type(llcx, tcx, elem), len as core::ffi::c_uint)
+        }
+        TyKind::Adt(..) | TyKind::Tuple(..) | TyKind::Closure(..) | TyKind::Generator(..) => {
+            aggregate_to_llvm_type(llcx, tcx, ty)
+        }
         _ => todo!(),
     }
 }
 
+/// Lowers a pointer to `pointee`. Pointers to unsized types become a `{ data, metadata }` pair.
+unsafe fn pointer_to_llvm_type<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    pointee: Ty<'tcx>,
+) -> LLVMTypeRef {
+    let pointee_llty = ty_to_llvm_type(llcx, tcx, pointee);
+    let data = match LLVMGetTypeKind(pointee_llty) {
+        LLVMTypeKind::LLVMVoidTypeKind => i8p_type(llcx),
+        _ => LLVMPointerType(pointee_llty, 0),
+    };
+    if !has_pointer_metadata(tcx, pointee) {
+        return data;
+    }
+
+    let meta = match tcx
+        .struct_tail_erasing_lifetimes(pointee, ParamEnv::reveal_all())
+        .kind()
+    {
+        TyKind::Dynamic(..) => LLVMPointerType(i8p_type(llcx), 0),
+        TyKind::Slice(..) | TyKind::Str => isize_type(llcx, tcx),
+        _ => todo!(),
+    };
+    let mut fields = [data, meta];
+    LLVMStructTypeInContext(
+        llcx,
+        fields.as_mut_ptr(),
+        fields.len() as core::ffi::c_uint,
+        0,
+    )
+}
+
+/// Returns whether pointers to `pointee` are fat. Extern types are unsized, but have no metadata.
+pub(crate) fn has_pointer_metadata<'tcx>(tcx: TyCtxt<'tcx>, pointee: Ty<'tcx>) -> bool {
+    let tail = tcx.struct_tail_erasing_lifetimes(pointee, ParamEnv::reveal_all());
+    !layout_of(tcx, pointee).is_sized() && !matches!(tail.kind(), TyKind::Foreign(..))
+}
+
+/// Lowers structs, enums, tuples, closures and generators to named LLVM structs.
+///
+/// The LLVM context doubles as the type cache, which also lets recursive types refer to themselves.
+unsafe fn aggregate_to_llvm_type<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+) -> LLVMTypeRef {
+    let layout = layout_of(tcx, ty);
+    if let Some(field) = newtype_field(tcx, layout) {
+        return ty_to_llvm_type(llcx, tcx, field.ty);
+    }
+
+    let name = c_string!(type_name(tcx, ty));
+    let llty = LLVMGetTypeByName2(llcx, name.as_ptr());
+    if !llty.is_null() {
+        return llty;
+    }
+
+    let llty = LLVMStructCreateNamed(llcx, name.as_ptr());
+    let mut fields = match (&layout.variants, &layout.fields) {
+        // Union fields are read and written through pointers cast to their type.
+        (Variants::Single { .. }, FieldsShape::Union(_)) => vec![LLVMArrayType(
+            LLVMInt8TypeInContext(llcx),
+            layout.size.bytes() as core::ffi::c_uint,
+        )],
+        (Variants::Multiple { .. }, _) => opaque_fields(llcx, layout),
+        (Variants::Single { .. }, _) => struct_fields(llcx, tcx, layout),
+    };
+    // Fields are placed at their offsets by explicit padding, which LLVM must not add to.
+    let packed = matches!(ty.kind(), TyKind::Adt(adt_def, _) if adt_def.repr().packed());
+    LLVMStructSetBody(
+        llty,
+        fields.as_mut_ptr(),
+        fields.len() as core::ffi::c_uint,
+        packed as LLVMBool,
+    );
+
+    llty
+}
+
+/// Returns the name of the LLVM struct of `ty`, which its vtables are named after as well.
+/// Closures and generators print without the substs they were instantiated with, so types
+/// containing them are suffixed with their type id hash.
+pub(crate) fn type_name<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> String {
+    let prints_all_substs = ty.walk().all(|arg| match arg.unpack() {
+        GenericArgKind::Type(ty) => {
+            !matches!(ty.kind(), TyKind::Closure(..) | TyKind::Generator(..))
+        }
+        _ => true,
+    });
+    if prints_all_substs {
+        ty.to_string()
+    } else {
+        format!("{}.{:016x}", ty, tcx.type_id_hash(ty))
+    }
+}
+
+/// Fields of enum variants are accessed by offset, so only the size and alignment of the whole
+/// type matter.
+unsafe fn opaque_fields<'tcx>(llcx: LLVMContextRef, layout: TyAndLayout<'tcx>) -> Vec<LLVMTypeRef> {
+    let unit = LLVMIntTypeInContext(llcx, layout.align.abi.bits() as core::ffi::c_uint);
+    let len = layout.size.bytes() / layout.align.abi.bytes();
+    vec![LLVMArrayType(unit, len as core::ffi::c_uint)]
+}
+
+/// Lays out the non-zero-sized fields of `layout` in memory order, with padding in between.
+unsafe fn struct_fields<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    layout: TyAndLayout<'tcx>,
+) -> Vec<LLVMTypeRef> {
+    let padding = |size: Size| LLVMArrayType(LLVMInt8TypeInContext(llcx), size.bytes() as _);
+
+    let mut fields = Vec::new();
+    let mut offset = Size::ZERO;
+    for idx in layout.fields.index_by_increasing_offset() {
+        let field = layout.field(&layout_cx(tcx), idx);
+        if field.is_zst() {
+            continue;
+        }
+        let field_offset = layout.fields.offset(idx);
+        if field_offset > offset {
+            fields.push(padding(field_offset - offset));
+            offset = field_offset;
+        }
+        // The unsized tail of a custom DST only contributes its alignment to the sized prefix.
+        if field.is_unsized() {
+            if let TyKind::Slice(..) | TyKind::Str = field.ty.kind() {
+                fields.push(LLVMArrayType(ty_to_llvm_type(llcx, tcx, field.ty), 0));
+            }
+            continue;
+        }
+        fields.push(ty_to_llvm_type(llcx, tcx, field.ty));
+        offset = field_offset + field.size;
+    }
+    if layout.size > offset {
+        fields.push(padding(layout.size - offset));
+    }
+
+    fields
+}
+
+/// Returns the index of the LLVM element that `struct_fields` lays out for field `idx` of `layout`.
+pub(crate) fn llvm_field_index<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    layout: TyAndLayout<'tcx>,
+    idx: usize,
+) -> core::ffi::c_uint {
+    let mut llidx = 0;
+    let mut offset = Size::ZERO;
+    for field_idx in layout.fields.index_by_increasing_offset() {
+        let field = layout.field(&layout_cx(tcx), field_idx);
+        if field.is_zst() {
+            continue;
+        }
+        let field_offset = layout.fields.offset(field_idx);
+        if field_offset > offset {
+            llidx += 1;
+        }
+        if field_idx == idx {
+            return llidx;
+        }
+        llidx += 1;
+        offset = field_offset + field.size;
+    }
+
+    unreachable!("field {} of `{}` has no LLVM element", idx, layout.ty)
+}
+
+/// Returns the only non-zero-sized field of a struct-like scalar type, e.g. `NonNull<T>` or
+/// `Unique<T>`. Such newtypes are lowered to the type of that field so that they can be immediates.
+pub(crate) fn newtype_field<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    layout: TyAndLayout<'tcx>,
+) -> Option<TyAndLayout<'tcx>> {
+    if !matches!(layout.variants, Variants::Single { .. })
+        || !matches!(layout.abi, Abi::Scalar(..) | Abi::ScalarPair(..))
+    {
+        return None;
+    }
+    if let TyKind::Adt(adt_def, _) = layout.ty.kind() {
+        if !adt_def.is_struct() {
+            return None;
+        }
+    }
+
+    let mut fields = (0..layout.fields.count())
+        .map(|idx| layout.field(&layout_cx(tcx), idx))
+        .filter(|field| !field.is_zst());
+    match (fields.next(), fields.next()) {
+        (Some(field), None) if field.size == layout.size => Some(field),
+        _ => None,
+    }
+}
+
 pub(crate) unsafe fn fn_sig_to_llvm_fn_type<'tcx>(
     llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
     fn_sig: FnSig<'tcx>,
 ) -> LLVMTypeRef {
     let mut inputs = fn_sig
         .inputs()
         .iter()
-        .map(|input| ty_to_llvm_type(llcx, *input))
+        .filter_map(|input| arg_to_llvm_type(llcx, tcx, *input))
         .collect::<Vec<_>>();
-    let output = ty_to_llvm_type(llcx, fn_sig.output());
+    let output = ret_to_llvm_type(llcx, tcx, fn_sig.output());
 
     LLVMFunctionType(
         output,
@@ -49,3 +312,126 @@ pub(crate) unsafe fn fn_sig_to_llvm_fn_type<'tcx>(
         0,
     )
 }
+
+/// Returns the LLVM type of a method called through a vtable, whose receiver is a thin `i8*`.
+pub(crate) unsafe fn virtual_fn_type<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    fn_sig: FnSig<'tcx>,
+) -> LLVMTypeRef {
+    let mut inputs = std::iter::once(i8p_type(llcx))
+        .chain(
+            fn_sig.inputs()[1..]
+                .iter()
+                .filter_map(|input| arg_to_llvm_type(llcx, tcx, *input)),
+        )
+        .collect::<Vec<_>>();
+    let output = ret_to_llvm_type(llcx, tcx, fn_sig.output());
+
+    LLVMFunctionType(
+        output,
+        inputs.as_mut_ptr(),
+        inputs.len() as core::ffi::c_uint,
+        0,
+    )
+}
+
+/// Zero-sized arguments are not passed at all.
+unsafe fn arg_to_llvm_type<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+) -> Option<LLVMTypeRef> {
+    (!layout_of(tcx, ty).is_zst()).then(|| ty_to_llvm_type(llcx, tcx, ty))
+}
+
+/// Functions returning a zero-sized type return `void`.
+unsafe fn ret_to_llvm_type<'tcx>(
+    llcx: LLVMContextRef,
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+) -> LLVMTypeRef {
+    if layout_of(tcx, ty).is_zst() {
+        LLVMVoidTypeInContext(llcx)
+    } else {
+        ty_to_llvm_type(llcx, tcx, ty)
+    }
+}
+
+pub(crate) fn instance_fn_sig<'tcx>(tcx: TyCtxt<'tcx>, instance: Instance<'tcx>) -> FnSig<'tcx> {
+    let ty = instance.ty(tcx, ParamEnv::reveal_all());
+    if let TyKind::Generator(_, substs, _) = ty.kind() {
+        return generator_resume_sig(tcx, ty, substs.as_generator().poly_sig());
+    }
+
+    let fn_sig = match ty.kind() {
+        // Closures take their environment before the arguments of their signature.
+        TyKind::Closure(def_id, substs) => {
+            let sig = substs.as_closure().sig();
+            let sig = tcx.normalize_erasing_late_bound_regions(ParamEnv::reveal_all(), sig);
+            let env_ty = tcx
+                .closure_env_ty(*def_id, substs, tcx.lifetimes.re_erased)
+                .unwrap();
+            tcx.mk_fn_sig(
+                std::iter::once(env_ty).chain(sig.inputs().iter().copied()),
+                sig.output(),
+                sig.c_variadic,
+                sig.unsafety,
+                sig.abi,
+            )
+        }
+        _ => tcx.normalize_erasing_late_bound_regions(ParamEnv::reveal_all(), ty.fn_sig(tcx)),
+    };
+
+    let mut inputs = fn_sig.inputs().to_vec();
+    // "rust-call" functions, like closures and the `Fn*` methods, take their arguments as a tuple,
+    // whose fields are passed one by one.
+    if fn_sig.abi == FnAbi::RustCall {
+        let tupled = inputs.pop().unwrap();
+        inputs.extend(tupled.tuple_fields());
+    }
+    // The shim takes `self` by pointer so that it can be called through a vtable.
+    if let InstanceDef::VTableShim(..) = instance.def {
+        inputs[0] = tcx.mk_mut_ptr(inputs[0]);
+    }
+    // `#[track_caller]` functions take the location of their caller after their arguments.
+    if instance.def.requires_caller_location(tcx) {
+        inputs.push(tcx.caller_location_ty());
+    }
+
+    tcx.mk_fn_sig(
+        inputs.into_iter(),
+        fn_sig.output(),
+        fn_sig.c_variadic,
+        fn_sig.unsafety,
+        fn_sig.abi,
+    )
+}
+
+/// Returns the signature of the resume function of the generator `ty`, i.e.
+/// `fn(Pin<&mut G>, Resume) -> GeneratorState<Yield, Return>`.
+fn generator_resume_sig<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+    sig: ty::PolyGenSig<'tcx>,
+) -> FnSig<'tcx> {
+    let sig = tcx.normalize_erasing_late_bound_regions(ParamEnv::reveal_all(), sig);
+
+    let pin = tcx.adt_def(tcx.require_lang_item(LangItem::Pin, None));
+    let env_ty = tcx.mk_mut_ref(tcx.lifetimes.re_erased, ty);
+    let env_ty = tcx.mk_adt(pin, tcx.intern_substs(&[env_ty.into()]));
+
+    let state = tcx.adt_def(tcx.require_lang_item(LangItem::GeneratorState, None));
+    let ret_ty = tcx.mk_adt(
+        state,
+        tcx.intern_substs(&[sig.yield_ty.into(), sig.return_ty.into()]),
+    );
+
+    tcx.mk_fn_sig(
+        [env_ty, sig.resume_ty].into_iter(),
+        ret_ty,
+        false,
+        Unsafety::Normal,
+        FnAbi::Rust,
+    )
+}
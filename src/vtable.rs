@@ -0,0 +1,132 @@
+use rustc_middle::ty::{
+    ExistentialTraitRef, Instance, PolyExistentialTraitRef, Ty, TyCtxt, VtblEntry,
+};
+
+use llvm_sys::{core::*, prelude::*, LLVMLinkage, LLVMUnnamedAddr};
+
+use std::ffi::CString;
+
+use crate::{
+    c_string,
+    codegen::get_fn,
+    ty::{i8p_type, isize_type, layout_of, type_name},
+};
+
+/// Returns the vtable of `ty` for `trait_ref` as an `i8**`, emitting it if it does not exist yet.
+///
+/// Entries are laid out as `TyCtxt::vtable_entries` orders them: drop-in-place, size and align,
+/// followed by the methods and the vtables of supertraits.
+pub(crate) unsafe fn get_vtable<'tcx>(
+    llcx: LLVMContextRef,
+    llmod: LLVMModuleRef,
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+    trait_ref: Option<PolyExistentialTraitRef<'tcx>>,
+) -> LLVMValueRef {
+    let i8p = i8p_type(llcx);
+    let name = match trait_ref {
+        Some(trait_ref) => c_string!(format!("vtable.<{} as {}>", type_name(tcx, ty), trait_ref)),
+        None => c_string!("vtable.", type_name(tcx, ty)),
+    };
+    let global = LLVMGetNamedGlobal(llmod, name.as_ptr());
+    if !global.is_null() {
+        return LLVMConstBitCast(global, LLVMPointerType(i8p, 0));
+    }
+
+    let entries = match trait_ref {
+        Some(trait_ref) => {
+            let trait_ref = trait_ref.with_self_ty(tcx, ty);
+            let trait_ref = tcx.erase_regions(trait_ref);
+            tcx.vtable_entries(trait_ref)
+        }
+        None => TyCtxt::COMMON_VTABLE_ENTRIES,
+    };
+
+    // Methods may refer to this very vtable, so it has to be declared before they are generated.
+    let global = LLVMAddGlobal(
+        llmod,
+        LLVMArrayType(i8p, entries.len() as core::ffi::c_uint),
+        name.as_ptr(),
+    );
+    LLVMSetGlobalConstant(global, 1);
+    LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+    LLVMSetUnnamedAddress(global, LLVMUnnamedAddr::LLVMGlobalUnnamedAddr);
+
+    let layout = layout_of(tcx, ty);
+    let usize_const =
+        |val: u64| LLVMConstIntToPtr(LLVMConstInt(isize_type(llcx, tcx), val, 0), i8p);
+    let mut llentries = entries
+        .iter()
+        .map(|entry| match entry {
+            VtblEntry::MetadataDropInPlace => {
+                let drop_in_place = Instance::resolve_drop_in_place(tcx, ty);
+                LLVMConstBitCast(get_fn(llcx, llmod, tcx, drop_in_place), i8p)
+            }
+            VtblEntry::MetadataSize => usize_const(layout.size.bytes()),
+            VtblEntry::MetadataAlign => usize_const(layout.align.abi.bytes()),
+            VtblEntry::Vacant => LLVMConstNull(i8p),
+            VtblEntry::Method(instance) => {
+                LLVMConstBitCast(get_fn(llcx, llmod, tcx, *instance), i8p)
+            }
+            VtblEntry::TraitVPtr(trait_ref) => {
+                let trait_ref = trait_ref
+                    .map_bound(|trait_ref| ExistentialTraitRef::erase_self_ty(tcx, trait_ref));
+                LLVMConstBitCast(get_vtable(llcx, llmod, tcx, ty, Some(trait_ref)), i8p)
+            }
+        })
+        .collect::<Vec<_>>();
+    LLVMSetInitializer(
+        global,
+        LLVMConstArray(
+            i8p,
+            llentries.as_mut_ptr(),
+            llentries.len() as core::ffi::c_uint,
+        ),
+    );
+
+    LLVMConstBitCast(global, LLVMPointerType(i8p, 0))
+}
+
+/// Loads the entry at `idx` of `vtable`.
+pub(crate) unsafe fn load_vtable_entry(
+    llcx: LLVMContextRef,
+    llbx: LLVMBuilderRef,
+    vtable: LLVMValueRef,
+    idx: usize,
+) -> LLVMValueRef {
+    let i8p = i8p_type(llcx);
+    let mut indices = [LLVMConstInt(LLVMInt32TypeInContext(llcx), idx as u64, 0)];
+    let entry = LLVMBuildInBoundsGEP2(
+        llbx,
+        i8p,
+        vtable,
+        indices.as_mut_ptr(),
+        indices.len() as core::ffi::c_uint,
+        c_string!("").as_ptr(),
+    );
+    LLVMBuildLoad2(llbx, i8p, entry, c_string!("").as_ptr())
+}
+
+/// Loads the method at `idx` of `vtable` as a pointer to a function of type `llfn_ty`.
+pub(crate) unsafe fn load_virtual_fn(
+    llcx: LLVMContextRef,
+    llbx: LLVMBuilderRef,
+    vtable: LLVMValueRef,
+    idx: usize,
+    llfn_ty: LLVMTypeRef,
+) -> LLVMValueRef {
+    let llfn = load_vtable_entry(llcx, llbx, vtable, idx);
+    LLVMBuildBitCast(
+        llbx,
+        llfn,
+        LLVMPointerType(llfn_ty, 0),
+        c_string!("").as_ptr(),
+    )
+}
+
+/// The vtable slot holding `drop_in_place`.
+pub(crate) const DROP_IN_PLACE: usize = 0;
+/// The vtable slot holding the size of the type.
+pub(crate) const SIZE: usize = 1;
+/// The vtable slot holding the alignment of the type.
+pub(crate) const ALIGN: usize = 2;
@@ -0,0 +1,11 @@
+// compile-flags: --execute -C overflow-checks=on
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+fn asserts() -> i32 {
+    checked(-7, 2)
+}
+
+// Division and remainder are checked for zero and overflow, the rest only for overflow.
+fn checked(a: i32, b: i32) -> i32 {
+    a / b + a % b - -a + (b as u8 * 100 + 55) as i32
+}
@@ -0,0 +1,17 @@
+#![feature(intrinsics)]
+
+extern "rust-intrinsic" {
+    fn atomic_load_acquire<T: Copy>(src: *const T) -> T;
+    fn atomic_store_release<T: Copy>(dst: *mut T, val: T);
+    fn atomic_xadd_seqcst<T: Copy>(dst: *mut T, src: T) -> T;
+    fn atomic_fence_seqcst();
+}
+
+fn atomics(ptr: *mut u32) -> u32 {
+    unsafe {
+        atomic_store_release(ptr, 1);
+        atomic_fence_seqcst();
+        atomic_xadd_seqcst(ptr, 2);
+        atomic_load_acquire(ptr)
+    }
+}
@@ -0,0 +1,47 @@
+// compile-flags: --execute
+// edition:2021
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+fn block_on_async() -> u32 {
+    let offset = 1;
+    let add_offset = |value: u32| value + offset;
+    let double: fn(u32) -> u32 = |value| value * 2;
+    let widened = block_on(widen(1u8, 2u8)) + block_on(widen(10u64, 20u64));
+    double(add_offset(block_on(add(1, 2)))) * 100 + widened as u32
+}
+
+async fn add(a: u32, b: u32) -> u32 {
+    ready(a).await + ready(b).await
+}
+
+// Each instantiation has its own state machine, which keeps `b` across the first await.
+async fn widen<T: Into<u64>>(a: T, b: T) -> u64 {
+    ready(a.into()).await + ready(b.into()).await
+}
+
+async fn ready<T>(value: T) -> T {
+    value
+}
+
+/// Polls `future` until it completes, with a waker that does nothing.
+fn block_on<F: Future>(mut future: F) -> F::Output {
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+
+    let waker = unsafe { Waker::from_raw(clone(std::ptr::null())) };
+    let mut cx = Context::from_waker(&waker);
+    // The future is shadowed, so it is never moved again.
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
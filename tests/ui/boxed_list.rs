@@ -0,0 +1,19 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+enum List {
+    Cons(u32, Box<List>),
+    Nil,
+}
+
+fn boxed_list() -> u32 {
+    let list = List::Cons(1, Box::new(List::Cons(2, Box::new(List::Nil))));
+    sum(&list)
+}
+
+fn sum(list: &List) -> u32 {
+    match list {
+        List::Cons(value, next) => value + sum(next),
+        List::Nil => 0,
+    }
+}
@@ -0,0 +1,15 @@
+#![feature(core_intrinsics)]
+
+use std::intrinsics::unlikely;
+
+fn branch_weights(x: u32) -> u32 {
+    if unlikely(x == 0) {
+        return fail();
+    }
+    x + 1
+}
+
+#[cold]
+fn fail() -> u32 {
+    0
+}
@@ -0,0 +1,19 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+// 128-bit division, overflow checks and `powi` are lowered to calls into compiler-builtins.
+fn builtins() -> i128 {
+    signed(-7, 3) + unsigned(u128::MAX, 1 << 100) as i128 + powi(1.5, 2) as i128
+}
+
+fn signed(a: i128, b: i128) -> i128 {
+    a / b * 100 + a % b * 10 + a.checked_mul(i128::MAX).unwrap_or(1000)
+}
+
+fn unsigned(a: u128, b: u128) -> u128 {
+    a / b
+}
+
+fn powi(x: f64, n: i32) -> f64 {
+    x.powi(n)
+}
@@ -0,0 +1,28 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+fn casts() -> i64 {
+    numeric(300, -1.5) + addresses(5) + fn_pointers(0xff)
+}
+
+// Integer casts truncate or extend by the signedness of their source, and float-to-int casts
+// saturate.
+fn numeric(int: i32, float: f32) -> i64 {
+    let truncated = int as u8 as i64;
+    let sign_extended = float as i8 as u32 as i64;
+    let saturated = (int as f64 * 1e10) as i32 as i64 + float as u8 as i64;
+    let converted = (int as f32 / 2.0) as i64;
+    truncated + sign_extended + saturated + converted
+}
+
+fn addresses(byte: u8) -> i64 {
+    let addr = &byte as *const u8 as usize;
+    unsafe { *(addr as *const u8) as i64 }
+}
+
+fn fn_pointers(bits: u64) -> i64 {
+    let count_ones: fn(u64) -> u32 = u64::count_ones;
+    let count_ones: unsafe fn(u64) -> u32 = count_ones;
+    let rotate: fn(i64) -> i64 = |value| value.rotate_left(1);
+    unsafe { count_ones(bits) as i64 + rotate(i64::MIN + 1) }
+}
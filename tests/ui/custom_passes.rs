@@ -0,0 +1,5 @@
+// compile-flags: -C passes=sroa
+
+fn custom_passes(a: i32, b: i32) -> i32 {
+    if a > b { a } else { b }
+}
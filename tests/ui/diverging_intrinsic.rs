@@ -0,0 +1,12 @@
+#![feature(intrinsics)]
+
+extern "rust-intrinsic" {
+    fn abort() -> !;
+}
+
+fn diverging_intrinsic(fail: bool) -> u32 {
+    if fail {
+        unsafe { abort() }
+    }
+    1
+}
@@ -0,0 +1,18 @@
+// normalize-stderr-test "17h[0-9a-f]{16}E" -> "17hHASHE"
+
+trait Shape {
+    fn area(&self) -> u32;
+}
+
+struct Square(u32);
+
+impl Shape for Square {
+    fn area(&self) -> u32 {
+        self.0 * self.0
+    }
+}
+
+fn dyn_call(square: &Square) -> u32 {
+    let shape: &dyn Shape = square;
+    shape.area()
+}
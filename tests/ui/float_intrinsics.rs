@@ -0,0 +1,11 @@
+#![feature(intrinsics)]
+
+extern "rust-intrinsic" {
+    fn sqrtf64(x: f64) -> f64;
+    fn powif64(a: f64, x: i32) -> f64;
+    fn fmaf64(a: f64, b: f64, c: f64) -> f64;
+}
+
+fn float_intrinsics(x: f64) -> f64 {
+    unsafe { fmaf64(sqrtf64(x), powif64(x, 2), x) }
+}
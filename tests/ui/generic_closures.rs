@@ -0,0 +1,13 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+// Both closures print as the same type, but capture values of different sizes.
+fn generic_closures() -> u64 {
+    let small: Box<dyn Fn() -> u64> = Box::new(capture(7u8));
+    let large: Box<dyn Fn() -> u64> = Box::new(capture(1u64 << 40));
+    small() + large()
+}
+
+fn capture<T: Copy + Into<u64>>(value: T) -> impl Fn() -> u64 {
+    move || value.into()
+}
@@ -0,0 +1,30 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn counted_allocations() -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let value = Box::new(42u64);
+    let bytes = Box::new([0u8; 16]);
+    ALLOCATIONS.load(Ordering::Relaxed) - before + *value as usize + bytes.len()
+}
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
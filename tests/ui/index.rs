@@ -0,0 +1,9 @@
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+// normalize-stderr-test "(?s)\n(; Function Attrs: [^\n]*\n)?define [^\n]*@.core::.*" -> ""
+// normalize-stderr-test "%.[^ ]*Location<[^>]*>." -> "%Location"
+// normalize-stderr-test "alloc[0-9]+" -> "allocN"
+
+fn index(xs: [u32; 4], i: usize) -> u32 {
+    let ys = [0; 3];
+    xs[i] + ys[i]
+}
@@ -0,0 +1,21 @@
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+// normalize-stderr-test "17h[0-9a-f]{16}E" -> "17hHASHE"
+
+use std::arch::asm;
+
+static VALUE: u64 = 42;
+
+fn inline_asm(x: u32) -> u32 {
+    let y: u32;
+    unsafe {
+        asm!(
+            "mov {y:e}, {x:e}",
+            "lea {tmp}, [rip + {value}]",
+            x = in(reg) x,
+            y = out(reg) y,
+            tmp = out(reg) _,
+            value = sym VALUE,
+        );
+    }
+    y
+}
@@ -0,0 +1,8 @@
+fn int_extension(a: i8, b: u16) -> i8 {
+    extended(a, b)
+}
+
+// The C calling convention extends integers narrower than 32 bits by their signedness.
+extern "C" fn extended(a: i8, b: u16) -> i8 {
+    a + b as i8
+}
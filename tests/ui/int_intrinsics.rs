@@ -0,0 +1,28 @@
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+
+#![feature(core_intrinsics)]
+
+use std::intrinsics::{add_with_overflow, exact_div, saturating_sub, unchecked_mul};
+
+fn int_intrinsics(a: u8, b: i8) {
+    overflows(a);
+    saturating(b);
+    unchecked(a);
+    exact(b);
+}
+
+fn overflows(a: u8) -> bool {
+    add_with_overflow(a, 1).1
+}
+
+fn saturating(b: i8) -> i8 {
+    saturating_sub(b, 1)
+}
+
+fn unchecked(a: u8) -> u8 {
+    unsafe { unchecked_mul(a, 3) }
+}
+
+fn exact(b: i8) -> i8 {
+    unsafe { exact_div(b, 2) }
+}
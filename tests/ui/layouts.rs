@@ -0,0 +1,21 @@
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+
+#[repr(packed)]
+struct Packed {
+    tag: u8,
+    value: u32,
+}
+
+#[repr(align(16))]
+struct Aligned(u32);
+
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn layouts(packed: &Packed) -> f32 {
+    let aligned = Aligned(packed.value);
+    let bits = Bits { int: aligned.0 };
+    unsafe { bits.float }
+}
@@ -0,0 +1,7 @@
+// compile-flags: -Z mir-enable-passes=-RemoveStorageMarkers
+
+fn lifetime_markers(x: u32) -> u32 {
+    let y = x;
+    let r = &y;
+    *r
+}
@@ -0,0 +1,18 @@
+#![feature(intrinsics)]
+
+extern "rust-intrinsic" {
+    fn copy_nonoverlapping<T>(src: *const T, dst: *mut T, count: usize);
+    fn copy<T>(src: *const T, dst: *mut T, count: usize);
+    fn write_bytes<T>(dst: *mut T, val: u8, count: usize);
+    fn volatile_load<T>(src: *const T) -> T;
+    fn volatile_store<T>(dst: *mut T, val: T);
+}
+
+fn memory(src: *const u32, dst: *mut u32, n: usize) {
+    unsafe {
+        copy_nonoverlapping(src, dst, n);
+        copy(src, dst, 2);
+        write_bytes(dst, 0, n);
+        volatile_store(dst, volatile_load(src));
+    }
+}
@@ -0,0 +1,9 @@
+// compile-flags: -C opt-level=3
+
+fn opt_level(x: i32) -> i32 {
+    double(x) + 1
+}
+
+fn double(x: i32) -> i32 {
+    x * 2
+}
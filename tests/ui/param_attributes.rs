@@ -0,0 +1,12 @@
+use std::cell::Cell;
+
+// Only `&u32` is `readonly`, since a `Cell` may be written through a shared reference.
+fn param_attributes(
+    shared: &u32,
+    unique: &mut u64,
+    _cell: &Cell<u32>,
+    boxed: Box<u16>,
+) -> Box<u16> {
+    *unique += *shared as u64;
+    boxed
+}
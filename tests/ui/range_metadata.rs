@@ -0,0 +1,11 @@
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+
+enum Level {
+    Low = 1,
+    High = 3,
+}
+
+// `bool` is an `i1` in memory too, so only the loads of `char` and of the tag get a `!range`.
+fn range_metadata(c: &char, flag: &bool, level: &Level) -> u32 {
+    *c as u32 + *flag as u32 + *level as u32
+}
@@ -0,0 +1,25 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+#![feature(intrinsics)]
+
+extern "rust-intrinsic" {
+    fn arith_offset<T>(dst: *const T, offset: isize) -> *const T;
+    fn const_eval_select<ARG, F, G, RET>(arg: ARG, called_in_const: F, called_at_rt: G) -> RET;
+}
+
+fn runtime_intrinsics() -> u64 {
+    let values = [1u64, 2, 3];
+    unsafe {
+        let last = *arith_offset(&values[0] as *const u64, 2);
+        const_eval_select((last,), in_const, at_runtime)
+    }
+}
+
+const fn in_const(x: u64) -> u64 {
+    x * 2
+}
+
+fn at_runtime(x: u64) -> u64 {
+    x * 2 + 1
+}
@@ -0,0 +1,14 @@
+#![feature(platform_intrinsics, repr_simd)]
+
+#[repr(simd)]
+#[derive(Clone, Copy)]
+struct U32x4(u32, u32, u32, u32);
+
+extern "platform-intrinsic" {
+    fn simd_add<T>(x: T, y: T) -> T;
+    fn simd_reduce_add_unordered<T, U>(x: T) -> U;
+}
+
+fn simd(a: U32x4, b: U32x4) -> u32 {
+    unsafe { simd_reduce_add_unordered(simd_add(a, b)) }
+}
@@ -0,0 +1,15 @@
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+// normalize-stderr-test "\[closure@[^\]]*\]" -> "[closure]"
+
+#![feature(fn_traits)]
+
+// `pair` is never borrowed, so it stays an SSA value whose fields are the call's arguments.
+fn ssa_pairs(x: u32) -> u32 {
+    let pair = split(x);
+    let add = |high: u32, low: u32| high + low;
+    add.call(pair)
+}
+
+fn split(x: u32) -> (u32, u32) {
+    (x, x + 1)
+}
@@ -0,0 +1,9 @@
+// normalize-stderr-test "(?s)\n(; Function Attrs: [^\n]*\n)?define [^\n]*@.core::.*" -> ""
+
+fn str_dedup() -> bool {
+    same("jit", "jit")
+}
+
+fn same(a: &str, b: &str) -> bool {
+    a.as_ptr() == b.as_ptr()
+}
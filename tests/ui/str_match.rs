@@ -0,0 +1,9 @@
+// normalize-stderr-test "core::cmp::PartialEq" -> "PartialEq"
+// normalize-stderr-test "(?s)\n(; Function Attrs: [^\n]*\n)?define [^\n]*@.<str as .*" -> ""
+
+fn is_yes(answer: &str) -> bool {
+    match answer {
+        "yes" | "y" => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,15 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+use std::cell::RefCell;
+
+// Its destructor is registered through the weak `__cxa_thread_atexit_impl` and `__dso_handle`.
+fn thread_local_drop() -> usize {
+    thread_local! {
+        static NAMES: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    }
+
+    NAMES.with(|names| names.borrow_mut().push(String::from("tachyon")));
+    NAMES.with(|names| names.borrow_mut().push(String::from("jit")));
+    NAMES.with(|names| names.borrow().concat().len())
+}
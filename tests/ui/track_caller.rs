@@ -0,0 +1,20 @@
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+// normalize-stderr-test "%.[^ ]*Location<[^>]*>." -> "%Location"
+// normalize-stderr-test "alloc[0-9]+" -> "allocN"
+
+#![feature(intrinsics)]
+
+use std::panic::Location;
+
+extern "rust-intrinsic" {
+    fn caller_location() -> &'static Location<'static>;
+}
+
+fn track_caller() -> &'static Location<'static> {
+    located()
+}
+
+#[track_caller]
+fn located() -> &'static Location<'static> {
+    unsafe { caller_location() }
+}
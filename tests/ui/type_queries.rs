@@ -0,0 +1,9 @@
+#![feature(intrinsics)]
+
+extern "rust-intrinsic" {
+    fn needs_drop<T>() -> bool;
+}
+
+fn type_queries() -> bool {
+    unsafe { needs_drop::<String>() }
+}
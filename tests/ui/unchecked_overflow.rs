@@ -0,0 +1,11 @@
+// compile-flags: --execute
+// normalize-stderr-test "(?s)^.*\[src/codegen\.rs:[0-9]+\] " -> ""
+
+// `<u8 as Add>::add` inherits the overflow checks of this crate, which has them off.
+fn unchecked_overflow() -> u8 {
+    add(200u8, 100)
+}
+
+fn add<T: std::ops::Add<Output = T>>(a: T, b: T) -> T {
+    a + b
+}
@@ -0,0 +1,14 @@
+// compile-flags: -Z mir-opt-level=0
+// normalize-stderr-test "(?s)(\n)\n[%@].*?\n(\ndefine)" -> "$1$2"
+
+fn unoptimized_mir(x: u32) -> u32 {
+    let pair = (x, x);
+    let mut n = pair.1;
+    while n > 10 {
+        n -= 10;
+    }
+    match n {
+        0 => 1,
+        _ => n,
+    }
+}
@@ -0,0 +1,30 @@
+#![feature(extern_types)]
+
+extern "C" {
+    type Opaque;
+}
+
+trait Value {}
+
+struct Tagged<T: ?Sized> {
+    tag: u8,
+    value: T,
+}
+
+fn tails(a: &Tagged<dyn Value>, b: &Tagged<[u16]>, c: &Opaque) {
+    dyn_tail(a);
+    slice_tail(b);
+    opaque(c);
+}
+
+fn dyn_tail(tagged: &Tagged<dyn Value>) -> &dyn Value {
+    &tagged.value
+}
+
+fn slice_tail(tagged: &Tagged<[u16]>) -> &[u16] {
+    &tagged.value
+}
+
+fn opaque(opaque: &Opaque) -> *const Opaque {
+    opaque
+}
@@ -0,0 +1,17 @@
+use std::marker::PhantomData;
+
+struct Marker;
+
+struct Tagged<T> {
+    value: u32,
+    tag: PhantomData<T>,
+}
+
+fn zst(marker: Marker, tagged: Tagged<Marker>) -> u32 {
+    let copy = marker;
+    take(copy, tagged.tag) + tagged.value
+}
+
+fn take(_: Marker, _: PhantomData<Marker>) -> u32 {
+    1
+}